@@ -1,116 +1,87 @@
 use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select};
 use std::fs;
-use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
-use std::process::Command;
-
-use crate::repo::{Repo, RepoStore};
-use crate::store::{self, ConfigStore};
-
-const GAM_MARKER_START: &str = "# >>> gamm";
-const GAM_MARKER_END: &str = "# <<< gamm";
-
-const GAM_HOOK_SECTION: &str = r#"# >>> gamm
-
-REMOTE_URL=$(git remote get-url origin 2>/dev/null || true)
-[ -z "$REMOTE_URL" ] && exit 0
-
-echo "gamm: checking ..."
-gamm pre-commit --repo "$REMOTE_URL"
-# <<< gamm"#;
-
-fn get_githooks_dir() -> PathBuf {
-    dirs::home_dir()
-        .expect("Could not find home directory")
-        .join(".githooks")
-}
-
-/// Get the current git user.email from global config
-fn get_current_git_email() -> Option<String> {
-    let output = Command::new("git")
-        .args(["config", "--global", "user.email"])
-        .output()
-        .ok()?;
-
-    if output.status.success() {
-        let email = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        if !email.is_empty() {
-            return Some(email);
-        }
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use crate::database::Store;
+use crate::doctor;
+use crate::document::{self, Format};
+use crate::git;
+use crate::hooks;
+use crate::identity;
+use crate::integration::{self, Integration};
+use crate::managed::{self, Managed};
+use crate::repo::{self, DirRule, MatchSource, Remote, RemoteConflict, RemoteMatch, Repo, RepoStore, Rule};
+use crate::settings::Settings;
+use crate::shim;
+use crate::status;
+use crate::store::{self, ConfigStore, Scope};
+use crate::sync;
+use crate::trailers;
+use crate::url::RemoteUrl;
+
+/// Apply git config for the given owner in the scope the settings select
+fn apply_git_config(config_store: &ConfigStore, owner: &str, settings: &Settings, repo_url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config_store.effective(owner)?;
+    let scope = settings.scope_for(&config);
+    if scope == Scope::Local && !git::in_work_tree() {
+        return Err("local scope requires running inside a git repository".into());
     }
 
-    None
-}
-
-/// Get the current git user.name from global config
-fn get_current_git_name() -> Option<String> {
-    let output = Command::new("git")
-        .args(["config", "--global", "user.name"])
-        .output()
-        .ok()?;
+    let entries = config.entries();
+    // Only what gamm wrote for the previous profile is removed
+    let stale = managed::stale(&managed::read(scope), &entries);
 
-    if output.status.success() {
-        let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        if !name.is_empty() {
-            return Some(name);
-        }
+    // Remember the global identity so the post-commit hook can put it back
+    if scope == Scope::Global && settings.restore_identity {
+        let keys: Vec<String> = entries
+            .iter()
+            .map(|(key, _)| key.clone())
+            .chain(stale.iter().map(|entry| match entry {
+                Managed::Key(key) | Managed::Value(key, _) => key.clone(),
+            }))
+            .collect();
+        identity::save_before_switch(owner, &keys)?;
     }
 
-    None
-}
-
-/// Apply git config for the given owner
-fn apply_git_config(owner: &str, config: &store::GitConfig, repo_url: &str) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Applying config '{}' for {}", owner, repo_url);
-
-    // Set user.name
-    if !config.user.name.is_empty() {
-        Command::new("git")
-            .args(["config", "--global", "user.name", &config.user.name])
-            .status()?;
-        println!("  user.name = {}", config.user.name);
-    }
+    println!("Applying config '{}' ({}) for {}", owner, scope, repo_url);
 
-    // Set user.email
-    if !config.user.email.is_empty() {
-        Command::new("git")
-            .args(["config", "--global", "user.email", &config.user.email])
-            .status()?;
-        println!("  user.email = {}", config.user.email);
+    let mut replaced: Vec<&str> = Vec::new();
+    for (key, value) in &entries {
+        let count = entries.iter().filter(|(k, _)| k == key).count();
+        if store::GitConfig::is_multi_valued(key) {
+            // Shared with rewrites the user added, so only add ours
+            if !git::config_get_all(scope, key).contains(value) {
+                git::config_add(scope, key, value)?;
+            }
+        } else if count > 1 {
+            // The profile owns every value of this key
+            if !replaced.contains(&key.as_str()) {
+                git::config_unset_all(scope, key)?;
+                replaced.push(key);
+            }
+            git::config_add(scope, key, value)?;
+        } else {
+            git::config_set(scope, key, value)?;
+        }
+        println!("  {} = {}", key, value);
     }
 
-    // Set commit.gpgsign
-    Command::new("git")
-        .args([
-            "config",
-            "--global",
-            "commit.gpgsign",
-            if config.commit.gpgsign { "true" } else { "false" },
-        ])
-        .status()?;
-    println!("  commit.gpgsign = {}", config.commit.gpgsign);
-
-    // Apply URL rewrites
-    for url_config in &config.urls {
-        Command::new("git")
-            .args([
-                "config",
-                "--global",
-                &format!("url.{}.insteadOf", url_config.pattern),
-                &url_config.instead_of,
-            ])
-            .status()?;
-        println!(
-            "  url.{}.insteadOf = {}",
-            url_config.pattern, url_config.instead_of
-        );
+    // Drop settings and URL rewrites left behind by the previous profile
+    for entry in managed::clean(scope, &stale)? {
+        match entry {
+            Managed::Key(key) => println!("  {} (unset)", key),
+            Managed::Value(key, value) => println!("  {} = {} (removed)", key, value),
+        }
     }
 
+    managed::record(scope, &entries)?;
     Ok(())
 }
 
-/// Show interactive UI to add a new config profile
-fn add_config_interactive(config_store: &mut ConfigStore) -> Result<String, Box<dyn std::error::Error>> {
+/// Show interactive UI to add a new config profile (asking for its name
+/// unless given). The caller commits the store.
+fn add_config_interactive(config_store: &mut ConfigStore, name: Option<String>) -> Result<String, Box<dyn std::error::Error>> {
     let theme = ColorfulTheme::default();
 
     println!();
@@ -120,77 +91,184 @@ fn add_config_interactive(config_store: &mut ConfigStore) -> Result<String, Box<
     println!();
 
     // Ask for profile name
-    let profile_name: String = Input::with_theme(&theme)
-        .with_prompt("Profile name (e.g., 'work', 'personal')")
-        .interact_text()?;
+    let profile_name = match name {
+        Some(name) => name,
+        None => Input::with_theme(&theme)
+            .with_prompt("Profile name (e.g., 'work', 'personal')")
+            .interact_text()?,
+    };
+
+    // Start from the current global identity
+    let mut defaults = store::GitConfig::default();
+    defaults.user.name = git::config_get(Scope::Global, "user.name").unwrap_or_default();
+    defaults.user.email = git::config_get(Scope::Global, "user.email").unwrap_or_default();
+    let git_config = prompt_profile(&theme, &defaults)?;
+
+    config_store.add(profile_name.clone(), git_config);
+
+    println!();
+    println!("✓ Config profile '{}' created!", profile_name);
+
+    Ok(profile_name)
+}
+
+/// Ask for the fields of a profile, pre-filled from `current`. What the
+/// prompts don't cover (URL rewrites, extra keys, `extends`, scope) is kept.
+fn prompt_profile(theme: &ColorfulTheme, current: &store::GitConfig) -> Result<store::GitConfig, Box<dyn std::error::Error>> {
+    // An empty name or email is only valid when it is inherited
+    let inherits = current.extends.is_some();
 
     // Ask for user.name
-    let default_name = get_current_git_name().unwrap_or_default();
-    let user_name: String = Input::with_theme(&theme)
+    let user_name: String = Input::with_theme(theme)
         .with_prompt("user.name")
-        .default(default_name)
+        .default(current.user.name.clone())
+        .allow_empty(inherits)
         .interact_text()?;
 
     // Ask for user.email
-    let default_email = get_current_git_email().unwrap_or_default();
-    let user_email: String = Input::with_theme(&theme)
+    let user_email: String = Input::with_theme(theme)
         .with_prompt("user.email")
-        .default(default_email)
+        .default(current.user.email.clone())
+        .allow_empty(inherits)
         .interact_text()?;
 
     // Ask for gpgsign
-    let gpgsign = Confirm::with_theme(&theme)
+    let gpgsign = Confirm::with_theme(theme)
         .with_prompt("Enable GPG signing for commits?")
-        .default(false)
+        .default(current.commit.gpgsign.unwrap_or(false))
         .interact()?;
 
-    // Create and save the config
-    let git_config = store::GitConfig {
+    // Signing details are only asked for when the profile signs
+    let mut signing = store::SigningConfig::default();
+    let mut tag_gpgsign = false;
+    if gpgsign {
+        let formats = store::GpgFormat::ALL;
+        let items: Vec<String> = formats.iter().map(|f| f.to_string()).collect();
+        let current_format = current.signing.format.unwrap_or_default();
+        let format = formats[Select::with_theme(theme)
+            .with_prompt("Signature format")
+            .items(&items)
+            .default(formats.iter().position(|f| *f == current_format).unwrap_or(0))
+            .interact()?];
+        signing.format = Some(format);
+
+        let key_hint = match format {
+            store::GpgFormat::Ssh => "path to the SSH public key",
+            store::GpgFormat::X509 => "certificate id or email",
+            store::GpgFormat::Openpgp => "key id",
+        };
+        let key: String = Input::with_theme(theme)
+            .with_prompt(format!("user.signingkey ({}, empty for git's default)", key_hint))
+            .with_initial_text(current.signing.key.clone().unwrap_or_default())
+            .allow_empty(true)
+            .interact_text()?;
+        signing.key = Some(key).filter(|k| !k.trim().is_empty());
+
+        let current_program = match format {
+            store::GpgFormat::Ssh => current.signing.ssh_program.clone(),
+            _ => current.signing.program.clone(),
+        };
+        let program: String = Input::with_theme(theme)
+            .with_prompt("Signing program (empty for git's default)")
+            .with_initial_text(current_program.unwrap_or_default())
+            .allow_empty(true)
+            .interact_text()?;
+        let program = Some(program).filter(|p| !p.trim().is_empty());
+        match format {
+            store::GpgFormat::Ssh => signing.ssh_program = program,
+            _ => signing.program = program,
+        }
+
+        tag_gpgsign = Confirm::with_theme(theme)
+            .with_prompt("Sign tags too?")
+            .default(current.tag.gpgsign.unwrap_or(current.commit.gpgsign != Some(true)))
+            .interact()?;
+    }
+
+    // Ask for the SSH key used for this profile's remotes
+    let home = dirs::home_dir();
+    let ssh_key: String = Input::with_theme(theme)
+        .with_prompt("SSH private key for this profile (empty for ssh's default)")
+        .with_initial_text(current.ssh_key.clone().unwrap_or_default())
+        .allow_empty(true)
+        .validate_with(|key: &String| -> Result<(), String> {
+            let key = key.trim();
+            if key.is_empty() || store::expand_home(key, home.as_deref()).is_file() {
+                Ok(())
+            } else {
+                Err(format!("no such file: {}", key))
+            }
+        })
+        .interact_text()?;
+    let ssh_key = Some(ssh_key.trim().to_string()).filter(|k| !k.is_empty());
+
+    // Ask for the sign-off and extra commit message trailers
+    let signoff = Confirm::with_theme(theme)
+        .with_prompt("Add a Signed-off-by trailer to commits?")
+        .default(current.user.signoff.is_some())
+        .interact()?
+        .then(|| current.user.signoff.clone().unwrap_or_else(|| "{name} <{email}>".to_string()));
+
+    let mut trailers = Vec::new();
+    if !current.trailers.is_empty() {
+        let listed: Vec<String> = current.trailers.iter().map(|t| format!("{}: {}", t.key, t.value)).collect();
+        let keep = Confirm::with_theme(theme)
+            .with_prompt(format!("Keep trailers {}?", listed.join(", ")))
+            .default(true)
+            .interact()?;
+        if keep {
+            trailers = current.trailers.clone();
+        }
+    }
+    loop {
+        let trailer: String = Input::with_theme(theme)
+            .with_prompt("Extra trailer, e.g. 'Change-Id: {change_id}' (empty to finish)")
+            .allow_empty(true)
+            .validate_with(|t: &String| -> Result<(), String> {
+                if t.trim().is_empty() {
+                    Ok(())
+                } else {
+                    store::TrailerConfig::parse(t).map(|_| ())
+                }
+            })
+            .interact_text()?;
+        if trailer.trim().is_empty() {
+            break;
+        }
+        trailers.push(store::TrailerConfig::parse(&trailer)?);
+    }
+
+    Ok(store::GitConfig {
         user: store::UserConfig {
             name: user_name,
             email: user_email,
-            signoff: None,
+            signoff,
         },
-        urls: vec![],
-        commit: store::CommitConfig { gpgsign },
-    };
-
-    config_store.add(profile_name.clone(), git_config.clone());
-    config_store.save()?;
-
-    // Apply the config to git immediately
-    println!();
-    println!("Applying config '{}'...", profile_name);
-
-    Command::new("git")
-        .args(["config", "--global", "user.name", &git_config.user.name])
-        .status()?;
-    println!("  user.name = {}", git_config.user.name);
-
-    Command::new("git")
-        .args(["config", "--global", "user.email", &git_config.user.email])
-        .status()?;
-    println!("  user.email = {}", git_config.user.email);
-
-    Command::new("git")
-        .args([
-            "config",
-            "--global",
-            "commit.gpgsign",
-            if git_config.commit.gpgsign { "true" } else { "false" },
-        ])
-        .status()?;
-    println!("  commit.gpgsign = {}", git_config.commit.gpgsign);
-
-    println!();
-    println!("✓ Config profile '{}' created and applied!", profile_name);
+        commit: store::CommitConfig { gpgsign: Some(gpgsign) },
+        signing,
+        tag: store::TagConfig { gpgsign: Some(tag_gpgsign) },
+        trailers,
+        ssh_key,
+        ..current.clone()
+    })
+}
 
-    Ok(profile_name)
+/// Friendly name for a repository: the last path segment of its URL
+fn default_repo_name(repo_url: &str) -> String {
+    repo_url
+        .rsplit('/')
+        .next()
+        .unwrap_or("repo")
+        .trim_end_matches(".git")
+        .to_string()
 }
 
-/// Show interactive UI to add a new repo to gam configuration
-fn add_repo_interactive(repo_url: &str, config_store: &mut ConfigStore) -> Result<Option<String>, Box<dyn std::error::Error>> {
+/// Show interactive UI to add a new repo to gam configuration. The answers
+/// are only collected in `store`; `save_answers` commits them afterwards.
+fn add_repo_interactive(repo_url: &str, store: &mut Store) -> Result<Option<String>, Box<dyn std::error::Error>> {
     let theme = ColorfulTheme::default();
+    let existing: Vec<String> = store.profiles.list().cloned().collect();
+    let config_store = &mut store.profiles;
 
     // Get list of available config profiles
     let mut profiles: Vec<String> = config_store.list().cloned().collect();
@@ -203,7 +281,7 @@ fn add_repo_interactive(repo_url: &str, config_store: &mut ConfigStore) -> Resul
         println!();
         println!("  Let's create your first config profile.");
 
-        let profile_name = add_config_interactive(config_store)?;
+        let profile_name = add_config_interactive(config_store, None)?;
         profiles.push(profile_name);
     }
 
@@ -222,20 +300,16 @@ fn add_repo_interactive(repo_url: &str, config_store: &mut ConfigStore) -> Resul
         .interact()?;
 
     if !add_repo {
+        // Keep a profile created above
+        save_answers(store, &existing, None)?;
         println!("Skipping repository setup.");
         return Ok(None);
     }
 
     // Ask for a friendly name for the repo
-    let default_name = repo_url
-        .rsplit('/')
-        .next()
-        .unwrap_or("repo")
-        .trim_end_matches(".git");
-
     let repo_name: String = Input::with_theme(&theme)
         .with_prompt("Enter a name for this repository")
-        .default(default_name.to_string())
+        .default(default_repo_name(repo_url))
         .interact_text()?;
 
     // Show selection for owner
@@ -247,7 +321,7 @@ fn add_repo_interactive(repo_url: &str, config_store: &mut ConfigStore) -> Resul
     let mut display_items: Vec<String> = profiles
         .iter()
         .map(|profile| {
-            if let Some(config) = config_store.get(profile) {
+            if let Ok(config) = config_store.effective(profile) {
                 format!("{} - {} <{}>", profile, config.user.name, config.user.email)
             } else {
                 profile.clone()
@@ -267,20 +341,18 @@ fn add_repo_interactive(repo_url: &str, config_store: &mut ConfigStore) -> Resul
     // Check if user selected "Create new profile"
     let selected_owner = if selection == profiles.len() {
         // Create new profile
-        let new_profile = add_config_interactive(config_store)?;
-        new_profile
+        add_config_interactive(config_store, None)?
     } else {
         profiles[selection].clone()
     };
 
-    // Save the repo to the store
-    let mut repo_store = RepoStore::load()?;
-    repo_store.add(Repo {
+    // Save the repo, and a profile created for it, in one commit
+    let repo = Repo {
         repo_name,
         url: repo_url.to_string(),
         commit_by: selected_owner.clone(),
-    });
-    repo_store.save()?;
+    };
+    save_answers(store, &existing, Some(repo))?;
 
     println!();
     println!("✓ Repository added with owner '{}'", selected_owner);
@@ -288,45 +360,138 @@ fn add_repo_interactive(repo_url: &str, config_store: &mut ConfigStore) -> Resul
     Ok(Some(selected_owner))
 }
 
-pub fn init() -> Result<(), Box<dyn std::error::Error>> {
-    let githooks_dir = get_githooks_dir();
-    let pre_commit_path = githooks_dir.join("pre-commit");
+/// Commit what `add_repo_interactive` collected: the profiles of `answers`
+/// that are not in `existing`, and the repository. The prompts run without
+/// the store lock, so the stores are loaded again under it and the answers
+/// added on top, keeping changes other gamm processes made meanwhile.
+fn save_answers(answers: &Store, existing: &[String], repo: Option<Repo>) -> Result<(), Box<dyn std::error::Error>> {
+    let created: Vec<_> = answers.profiles.iter().filter(|(name, _)| !existing.contains(name)).collect();
+    if created.is_empty() && repo.is_none() {
+        return Ok(());
+    }
 
-    // Create ~/.githooks directory if it doesn't exist
-    if !githooks_dir.exists() {
-        fs::create_dir_all(&githooks_dir)?;
-        println!("Created directory: {}", githooks_dir.display());
+    let _lock = crate::storage::lock()?;
+    let mut store = Store::load_from(&crate::storage::require_config_dir()?)?;
+    for (name, config) in created {
+        if store.profiles.get(name).is_some() {
+            return Err(format!("profile '{}' was added by another gamm process meanwhile; nothing was saved", name).into());
+        }
+        store.profiles.add(name.clone(), config.clone());
     }
+    if let Some(repo) = repo {
+        store.repos.add(repo);
+    }
+    store.commit()?;
+    sync::refresh(&store.profiles, &store.repos)?;
+    Ok(())
+}
 
-    let new_content = if pre_commit_path.exists() {
-        let existing = fs::read_to_string(&pre_commit_path)?;
-        
-        // Check if gam section already exists
-        if existing.contains(GAM_MARKER_START) {
-            println!("gamm hook already installed in: {}", pre_commit_path.display());
-            return Ok(());
+/// Refuse --config-dir for something gamm installs that can't carry it, so
+/// it would silently use another config dir than the one given
+fn refuse_config_dir_flag(what: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if crate::storage::config_dir_flag().is_some() {
+        return Err(format!(
+            "--config-dir doesn't reach {}; set GAMM_CONFIG_DIR in the environment instead",
+            what
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Add gamm to a hook manager's config in the current repository instead of
+/// installing global hooks (or only print the snippet)
+fn init_integration(tool: Integration, print: bool) -> Result<(), Box<dyn std::error::Error>> {
+    refuse_config_dir_flag("hooks run by a hook manager")?;
+    if print {
+        print!("{}", integration::snippet(tool));
+        return Ok(());
+    }
+
+    let toplevel = git::toplevel().ok_or("not inside a git repository; use --print to see the snippet")?;
+    let written = match integration::install(tool, &toplevel) {
+        Ok(written) => written,
+        Err(e) => {
+            println!("Add this by hand instead:");
+            println!();
+            print!("{}", integration::snippet(tool));
+            println!();
+            return Err(e.into());
         }
-        
-        // Append gam section to existing file
-        format!("{}\n\n{}\n", existing.trim_end(), GAM_HOOK_SECTION)
-    } else {
-        // Create new file with shebang
-        format!("#!/bin/sh\nset -e\n\n{}\n", GAM_HOOK_SECTION)
     };
 
-    fs::write(&pre_commit_path, new_content)?;
+    for (path, result) in written {
+        match result {
+            hooks::Installed::Created => println!("Created {}", path.display()),
+            hooks::Installed::Updated => println!("Added gamm to {}", path.display()),
+            hooks::Installed::Unchanged => println!("gamm already in {}", path.display()),
+        }
+    }
+
+    println!();
+    match tool {
+        Integration::PreCommit => {
+            println!("Install the hook types gamm uses:");
+            println!("  pre-commit install --hook-type pre-commit --hook-type commit-msg --hook-type post-commit");
+        }
+        Integration::Lefthook => println!("Run `lefthook install` to refresh the hooks."),
+        Integration::Husky => {}
+    }
+    if hooks::global_hooks_path() == hooks::HooksPath::Ours {
+        println!("Note: core.hooksPath still points at gamm's global hooks, so gamm runs twice.");
+        println!("  git config --global --unset core.hooksPath");
+    }
+
+    Ok(())
+}
+
+pub fn init(set_hooks_path: bool, integration: Option<Integration>, print: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(tool) = integration {
+        return init_integration(tool, print);
+    }
+
+    let githooks_dir = hooks::githooks_dir();
 
-    // Make the script executable (chmod +x)
-    let mut perms = fs::metadata(&pre_commit_path)?.permissions();
-    perms.set_mode(0o755);
-    fs::set_permissions(&pre_commit_path, perms)?;
+    for (name, body) in hooks::HOOKS {
+        let path = hooks::hook_path(name);
+        match hooks::install(name, body)? {
+            hooks::Installed::Created => println!("Installed {} hook: {}", name, path.display()),
+            hooks::Installed::Updated => println!("Updated {} hook: {}", name, path.display()),
+            hooks::Installed::Unchanged => println!("gamm hook already installed in: {}", path.display()),
+        }
+    }
 
-    println!("Installed pre-commit hook: {}", pre_commit_path.display());
     println!();
-    println!("To enable the hook globally, run:");
-    println!("  git config --global core.hooksPath ~/.githooks");
+    let current = hooks::global_hooks_path();
+    if current == hooks::HooksPath::Ours {
+        println!("core.hooksPath already points at {}", githooks_dir.display());
+    } else {
+        if let hooks::HooksPath::Other(path) = &current {
+            println!("core.hooksPath is currently set to {}.", path);
+            println!("Hooks in that directory stop running once it points at {};", githooks_dir.display());
+            println!("gamm's hooks only hand over to each repository's own hooks.");
+            println!();
+        }
+
+        // Only ask when someone can answer
+        let enable = set_hooks_path
+            || (std::io::stdin().is_terminal()
+                && Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Set the global core.hooksPath to {}?", githooks_dir.display()))
+                    .default(current == hooks::HooksPath::Unset)
+                    .interact()?);
+
+        if enable {
+            hooks::enable()?;
+            println!("✓ core.hooksPath set to {}", githooks_dir.display());
+            println!("  Repository hooks in .git/hooks (or a repository's own core.hooksPath) still run after gamm's.");
+        } else {
+            println!("To enable the hooks globally, run:");
+            println!("  git config --global core.hooksPath {}", githooks_dir.display());
+        }
+    }
 
-    if let Some(config_dir) = store::ConfigStore::config_dir() {
+    if let Some(config_dir) = crate::storage::config_dir() {
         println!();
         println!("Config storage: {}", config_dir.display());
     }
@@ -334,33 +499,73 @@ pub fn init() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-pub fn pre_commit(repo_url: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub fn pre_commit(repo_url: Option<&str>, repo_path: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
     // Load the stores
-    let repo_store = RepoStore::load()?;
-    let mut config_store = ConfigStore::load()?;
+    let mut store = Store::load()?;
+    let settings = Settings::load()?;
+
+    // An explicit --repo URL replaces remote discovery
+    let remotes = match repo_url {
+        Some(url) => vec![Remote {
+            name: "--repo".to_string(),
+            url: url.to_string(),
+        }],
+        None => git::ordered_remotes(&settings.remote_priority),
+    };
+    let repo_path = repo_path.map(Path::to_path_buf).or_else(git::toplevel);
 
-    // Check if the repo exists in the store
-    if let Some(owner) = repo_store.lookup_owner_by_url(repo_url) {
-        // Repo exists - verify owner matches current git config
-        let config = match config_store.get(owner) {
-            Some(config) => config,
-            None => {
-                eprintln!("Warning: repo mapped to config '{}' but config not found", owner);
-                return Ok(());
-            }
+    if repo_url.is_none()
+        && let Some(pinned) = git::config_get(Scope::Local, git::REMOTE_PIN_KEY)
+        && !remotes.iter().any(|r| r.name == pinned)
+    {
+        eprintln!("Warning: pinned remote '{}' does not exist, using all remotes", pinned);
+    }
+
+    // Check if the repo exists in the store or is covered by a URL or directory rule
+    let resolved = store.repos.resolve_remotes(&remotes, repo_path.as_deref(), &settings.remote_priority)?;
+
+    // What to call the repository in messages
+    let target = match (&resolved, remotes.first(), &repo_path) {
+        (Some(RemoteMatch { remote: Some(remote), .. }), _, _) => remote.url.clone(),
+        (_, Some(remote), _) => remote.url.clone(),
+        (_, None, Some(path)) => path.display().to_string(),
+        (_, None, None) => return Ok(()),
+    };
+
+    if let Some(RemoteMatch { remote, found }) = resolved {
+        let config_store = &store.profiles;
+        let owner = found.commit_by;
+        let mut via = match &found.source {
+            MatchSource::Exact => String::new(),
+            source => format!(" via {}", source),
         };
+        if let Some(remote) = remote.as_ref().filter(|r| remotes.len() > 1 || r.name != "origin") {
+            via.push_str(&format!(" (remote {})", remote.name));
+        }
+        // Repo exists - verify owner matches current git config
+        if config_store.get(owner).is_none() {
+            eprintln!("Warning: repo mapped to config '{}' but config not found", owner);
+            return Ok(());
+        }
+        let config = config_store.effective(owner)?;
+        let scope = settings.scope_for(&config);
 
-        // Get current git config
-        let current_email = get_current_git_email();
-        let current_name = get_current_git_name();
+        // Compare against the effective values so that an identity supplied
+        // by `gamm sync` includes counts as a match
+        let current_email = git::config_effective("user.email");
+        let current_name = git::config_effective("user.name");
 
         // Check if current config matches the expected owner config
-        let email_matches = current_email.as_ref().map_or(false, |e| e == &config.user.email);
-        let name_matches = current_name.as_ref().map_or(false, |n| n == &config.user.name);
+        let email_matches = current_email.as_ref().is_some_and(|e| e == &config.user.email);
+        let name_matches = current_name.as_ref().is_some_and(|n| n == &config.user.name);
 
         if email_matches && name_matches {
-            // Config already matches, nothing to do
-            println!("✓ Git config already set for '{}' ({})", owner, config.user.email);
+            // Config already matches; drop what gamm set for rewrites or
+            // keys removed since, which doesn't change the identity
+            println!("✓ Git config already set for '{}' ({}){}", owner, config.user.email, via);
+            for entry in managed::tidy(scope, &config.entries())? {
+                println!("  {} (removed)", entry);
+            }
             return Ok(());
         }
 
@@ -370,8 +575,13 @@ pub fn pre_commit(repo_url: &str) -> Result<(), Box<dyn std::error::Error>> {
             println!("│  Git config mismatch detected                               │");
             println!("└─────────────────────────────────────────────────────────────┘");
             println!();
-            println!("  Repository: {}", repo_url);
+            println!("  Repository: {}", target);
             println!("  Expected owner: {} ({})", owner, config.user.email);
+            println!("  Matched by: {}", found.source);
+            if let Some(remote) = &remote {
+                println!("  Remote: {}", remote.name);
+            }
+            println!("  Scope: {}", scope);
             println!();
 
             if let Some(ref email) = current_email {
@@ -394,20 +604,23 @@ pub fn pre_commit(repo_url: &str) -> Result<(), Box<dyn std::error::Error>> {
         }
 
         // Apply the config
-        apply_git_config(owner, config, repo_url)?;
-        
+        apply_git_config(config_store, owner, &settings, &target)?;
+
         // Abort the commit so user can retry with correct config
         println!();
         println!("⚠ Config updated. Please run your commit command again.");
         std::process::exit(1);
-    } else {
-        // Repo doesn't exist - show interactive UI to add it
-        if let Some(owner) = add_repo_interactive(repo_url, &mut config_store)? {
+    } else if let Some(remote) = remotes.first() {
+        // Repo doesn't exist - show interactive UI to add it, keyed by the
+        // highest-priority remote
+        let repo_url = remote.url.as_str();
+        if remotes.len() > 1 {
+            println!("Using remote '{}' ({})", remote.name, repo_url);
+        }
+        if let Some(owner) = add_repo_interactive(repo_url, &mut store)? {
             // Apply the config for the newly added repo
-            if let Some(config) = config_store.get(&owner) {
-                apply_git_config(&owner, config, repo_url)?;
-            }
-            
+            apply_git_config(&store.profiles, &owner, &settings, repo_url)?;
+
             // Abort the commit so user can retry with correct config
             println!();
             println!("⚠ Config applied. Please run your commit command again.");
@@ -415,6 +628,85 @@ pub fn pre_commit(repo_url: &str) -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // No remote and no directory rule: leave the commit alone
+    Ok(())
+}
+
+/// Resolve the repository in the current directory from its remotes and path
+fn resolve_current<'a>(
+    repo_store: &'a RepoStore,
+    settings: &Settings,
+) -> Result<Option<RemoteMatch<'a>>, RemoteConflict> {
+    let remotes = git::ordered_remotes(&settings.remote_priority);
+    let path = git::toplevel();
+    repo_store.resolve_remotes(&remotes, path.as_deref(), &settings.remote_priority)
+}
+
+/// prepare-commit-msg / commit-msg hooks: add the sign-off and trailers of
+/// the repository's profile to the message file
+pub fn commit_msg(file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let repo_store = RepoStore::load()?;
+    let config_store = ConfigStore::load()?;
+    let settings = Settings::load()?;
+
+    // Conflicts and unknown repositories are reported by the pre-commit hook
+    let Ok(Some(found)) = resolve_current(&repo_store, &settings) else {
+        return Ok(());
+    };
+    let owner = found.found.commit_by;
+    if let Ok(config) = config_store.effective(owner) {
+        trailers::add(file, owner, &config)?;
+    }
+
+    Ok(())
+}
+
+/// Run gamm's part of a git hook, for hook managers: the repository and its
+/// remotes are discovered from the current directory
+pub fn hook_run(name: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    match name {
+        hooks::PRE_COMMIT => pre_commit(None, None),
+        hooks::PREPARE_COMMIT_MSG | hooks::COMMIT_MSG => {
+            let file = args.first().ok_or_else(|| format!("the {} hook needs the message file", name))?;
+            commit_msg(Path::new(file))
+        }
+        hooks::POST_COMMIT => post_commit(),
+        _ => Err(format!(
+            "gamm has nothing to do in '{}' hooks (use {}, {}, {} or {})",
+            name,
+            hooks::PRE_COMMIT,
+            hooks::PREPARE_COMMIT_MSG,
+            hooks::COMMIT_MSG,
+            hooks::POST_COMMIT
+        )
+        .into()),
+    }
+}
+
+/// Post-commit hook: restore the global identity saved before the switch
+pub fn post_commit() -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(snapshot) = identity::restore_pending()? {
+        println!("gamm: restored global identity (was switched to '{}')", snapshot.profile);
+    }
+
+    Ok(())
+}
+
+/// Restore a pending global identity snapshot by hand
+pub fn restore() -> Result<(), Box<dyn std::error::Error>> {
+    match identity::restore_pending()? {
+        Some(snapshot) => {
+            println!("✓ Restored global identity saved before switching to '{}'", snapshot.profile);
+            for (key, values) in &snapshot.values {
+                match values.as_slice() {
+                    [] => println!("  {} (unset)", key),
+                    values => println!("  {} = {}", key, values.join(", ")),
+                }
+            }
+        }
+        None => println!("No saved global identity to restore."),
+    }
+
     Ok(())
 }
 
@@ -424,139 +716,841 @@ pub fn repo_list() -> Result<(), Box<dyn std::error::Error>> {
     let config_store = ConfigStore::load()?;
 
     let repos: Vec<_> = repo_store.iter().collect();
+    let rules = repo_store.rules_by_precedence();
+    let dir_rules = repo_store.dir_rules_by_precedence();
 
-    if repos.is_empty() {
+    if repos.is_empty() && rules.is_empty() && dir_rules.is_empty() {
         println!("No repositories configured.");
         println!();
         println!("Repositories are automatically added when you commit to a new repo.");
         return Ok(());
     }
 
+    let owner_info = |commit_by: &str| {
+        config_store
+            .effective(commit_by)
+            .ok()
+            .map(|c| format!("{} <{}>", c.user.name, c.user.email))
+            .unwrap_or_else(|| "(config not found)".to_string())
+    };
+
     println!();
     println!("┌─────────────────────────────────────────────────────────────┐");
     println!("│  Configured Repositories                                    │");
     println!("└─────────────────────────────────────────────────────────────┘");
     println!();
 
-    for (_url, repo) in repos {
-        let owner_info = config_store
-            .get(&repo.commit_by)
-            .map(|c| format!("{} <{}>", c.user.name, c.user.email))
-            .unwrap_or_else(|| "(config not found)".to_string());
-
+    for (url, repo) in repos {
         println!("  {} ", repo.repo_name);
         println!("    URL:   {}", repo.url);
-        println!("    Owner: {} ({})", repo.commit_by, owner_info);
+        println!("    Owner: {} ({})", repo.commit_by, owner_info(&repo.commit_by));
+        match repo_store.matching_rule(url) {
+            Some(rule) => println!("    Match: exact entry (takes precedence over rule {})", rule.pattern),
+            None => println!("    Match: exact entry"),
+        }
+        println!();
+    }
+
+    if !rules.is_empty() {
+        println!("  Rules (most specific first):");
+        println!();
+
+        for rule in rules {
+            println!("    {}", rule.pattern);
+            println!("      Owner: {} ({})", rule.commit_by, owner_info(&rule.commit_by));
+        }
+        println!();
+    }
+
+    if !dir_rules.is_empty() {
+        println!("  Directory rules (used when no URL entry or rule matches):");
+        println!();
+
+        for rule in dir_rules {
+            println!("    {}", rule.pattern);
+            println!("      Owner: {} ({})", rule.commit_by, owner_info(&rule.commit_by));
+        }
         println!();
     }
 
     Ok(())
 }
 
-/// Delete a repository configuration
-pub fn repo_delete(name: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut repo_store = RepoStore::load()?;
-    let repos: Vec<_> = repo_store.iter().map(|(url, repo)| (url.clone(), repo.clone())).collect();
+/// Add or replace a wildcard rule
+pub fn repo_rule_add(pattern: &str, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
 
-    if repos.is_empty() {
-        println!("No repositories configured.");
-        return Ok(());
+    Rule::validate(pattern)?;
+    if store.profiles.get(profile).is_none() {
+        return Err(format!("profile '{}' not found", profile).into());
     }
 
-    let to_delete = if let Some(ref name) = name {
-        // Find by name or URL
-        repos.iter()
-            .find(|(url, repo)| repo.repo_name == *name || url == name)
-            .map(|(url, _)| url.clone())
-    } else {
-        // Interactive selection
-        let theme = ColorfulTheme::default();
-
-        println!();
-        println!("Select a repository to delete:");
-        println!();
+    store.repos.add_rule(Rule {
+        pattern: pattern.to_string(),
+        commit_by: profile.to_string(),
+    });
+    store.commit()?;
+    sync::refresh(&store.profiles, &store.repos)?;
 
-        let items: Vec<String> = repos
-            .iter()
-            .map(|(_, repo)| format!("{} ({})", repo.repo_name, repo.url))
-            .collect();
+    println!("✓ Repositories matching '{}' now use profile '{}'", pattern, profile);
 
-        let selection = Select::with_theme(&theme)
-            .with_prompt("Choose repository")
-            .items(&items)
-            .default(0)
-            .interact()?;
+    Ok(())
+}
 
-        Some(repos[selection].0.clone())
-    };
+/// Remove a wildcard rule
+pub fn repo_rule_remove(pattern: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
 
-    match to_delete {
-        Some(url) => {
-            let repo = repo_store.remove_by_url(&url);
-            if let Some(repo) = repo {
-                repo_store.save()?;
-                println!("✓ Deleted repository '{}'", repo.repo_name);
-            }
-        }
-        None => {
-            if let Some(name) = name {
-                println!("Repository '{}' not found.", name);
-            }
+    match store.repos.remove_rule(pattern) {
+        Some(rule) => {
+            store.commit()?;
+            sync::refresh(&store.profiles, &store.repos)?;
+            println!("✓ Deleted rule '{}' ({})", rule.pattern, rule.commit_by);
         }
+        None => println!("Rule '{}' not found.", pattern),
     }
 
     Ok(())
 }
 
-/// List all configured profiles
-pub fn profile_list() -> Result<(), Box<dyn std::error::Error>> {
-    let config_store = ConfigStore::load()?;
-
-    let profiles: Vec<_> = config_store.iter().collect();
+/// List wildcard rules
+pub fn repo_rule_list() -> Result<(), Box<dyn std::error::Error>> {
+    let repo_store = RepoStore::load()?;
 
-    if profiles.is_empty() {
-        println!("No profiles configured.");
+    let rules = repo_store.rules_by_precedence();
+    if rules.is_empty() {
+        println!("No rules configured.");
         println!();
-        println!("Create a profile by running: gamm pre-commit --repo <url>");
+        println!("Add one with: gamm repo rule add github.com/<org>/* <profile>");
         return Ok(());
     }
 
-    println!();
-    println!("┌─────────────────────────────────────────────────────────────┐");
-    println!("│  Configured Profiles                                        │");
-    println!("└─────────────────────────────────────────────────────────────┘");
-    println!();
+    for rule in rules {
+        println!("  {} -> {}", rule.pattern, rule.commit_by);
+    }
 
-    for (name, config) in profiles {
-        println!("  {} ", name);
-        println!("    Name:     {}", config.user.name);
-        println!("    Email:    {}", config.user.email);
-        println!("    GPG Sign: {}", if config.commit.gpgsign { "yes" } else { "no" });
-        if !config.urls.is_empty() {
-            println!("    URL Rewrites:");
-            for url in &config.urls {
-                println!("      {} -> {}", url.instead_of, url.pattern);
-            }
-        }
-        println!();
+    Ok(())
+}
+
+/// Add or replace a directory rule
+pub fn repo_dir_add(pattern: &str, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
+
+    DirRule::validate(pattern)?;
+    if store.profiles.get(profile).is_none() {
+        return Err(format!("profile '{}' not found", profile).into());
     }
 
+    store.repos.add_dir_rule(DirRule {
+        pattern: pattern.to_string(),
+        commit_by: profile.to_string(),
+    });
+    store.commit()?;
+    sync::refresh(&store.profiles, &store.repos)?;
+
+    println!("✓ Repositories under '{}' now use profile '{}'", pattern, profile);
+
     Ok(())
 }
 
-/// Delete a profile configuration (also removes related repositories)
-pub fn profile_delete(name: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut config_store = ConfigStore::load()?;
-    let mut repo_store = RepoStore::load()?;
+/// Remove a directory rule
+pub fn repo_dir_remove(pattern: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
 
-    let profiles: Vec<_> = config_store.iter().map(|(n, _)| n.clone()).collect();
+    match store.repos.remove_dir_rule(pattern) {
+        Some(rule) => {
+            store.commit()?;
+            sync::refresh(&store.profiles, &store.repos)?;
+            println!("✓ Deleted directory rule '{}' ({})", rule.pattern, rule.commit_by);
+        }
+        None => println!("Directory rule '{}' not found.", pattern),
+    }
 
-    if profiles.is_empty() {
-        println!("No profiles configured.");
+    Ok(())
+}
+
+/// List directory rules
+pub fn repo_dir_list() -> Result<(), Box<dyn std::error::Error>> {
+    let repo_store = RepoStore::load()?;
+
+    let rules = repo_store.dir_rules_by_precedence();
+    if rules.is_empty() {
+        println!("No directory rules configured.");
+        println!();
+        println!("Add one with: gamm repo dir add '~/work/**' <profile>");
         return Ok(());
     }
 
-    let to_delete = if let Some(ref name) = name {
+    for rule in rules {
+        println!("  {} -> {}", rule.pattern, rule.commit_by);
+    }
+
+    Ok(())
+}
+
+/// Apply the identity of the repository in the current directory after its
+/// entry changed. Does nothing unless `url` is one of its remotes.
+fn apply_if_inside(url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let key = RemoteUrl::key_for(url);
+    if !git::remotes().iter().any(|(_, u)| RemoteUrl::key_for(u) == key) {
+        return Ok(());
+    }
+
+    let repo_store = RepoStore::load()?;
+    let config_store = ConfigStore::load()?;
+    let settings = Settings::load()?;
+
+    // Another remote may decide, so apply whatever the repository resolves to
+    match resolve_current(&repo_store, &settings) {
+        Ok(Some(found)) => {
+            let target = found.remote.as_ref().map_or(url, |r| r.url.as_str());
+            println!();
+            apply_git_config(&config_store, found.found.commit_by, &settings, target)?;
+        }
+        Ok(None) => {}
+        Err(conflict) => eprintln!("Warning: not applying an identity, {}", conflict),
+    }
+    Ok(())
+}
+
+/// Register a repository by URL, or by the remote of a local repository
+/// (the current one by default)
+pub fn repo_add(
+    url: Option<String>,
+    path: Option<&Path>,
+    profile: &str,
+    name: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
+    let settings = Settings::load()?;
+
+    if store.profiles.get(profile).is_none() {
+        return Err(format!("profile '{}' not found", profile).into());
+    }
+
+    let url = match url {
+        Some(url) => url,
+        None => {
+            // Read the remote from inside --path, then come back so the
+            // identity is only applied when run from within the repository
+            let cwd = std::env::current_dir()?;
+            if let Some(path) = path {
+                std::env::set_current_dir(path)
+                    .map_err(|e| format!("cannot use '{}': {}", path.display(), e))?;
+            }
+            let in_repo = git::in_work_tree();
+            let remote = git::ordered_remotes(&settings.remote_priority).into_iter().next();
+            std::env::set_current_dir(cwd)?;
+
+            if !in_repo {
+                return Err("not inside a git repository; pass --url or --path".into());
+            }
+            remote
+                .map(|remote| remote.url)
+                .ok_or("the repository has no remotes; pass --url, or use `gamm repo dir add` to match it by directory")?
+        }
+    };
+
+    if let Some(existing) = store.repos.find(&url) {
+        return Err(format!(
+            "'{}' is already registered as '{}' (profile '{}'); use `gamm repo set` to change it",
+            url, existing.repo_name, existing.commit_by
+        )
+        .into());
+    }
+
+    let repo_name = name.unwrap_or_else(|| default_repo_name(&url));
+    store.repos.add(Repo {
+        repo_name: repo_name.clone(),
+        url: url.clone(),
+        commit_by: profile.to_string(),
+    });
+    store.commit()?;
+    sync::refresh(&store.profiles, &store.repos)?;
+
+    println!("✓ Added repository '{}' ({}) with profile '{}'", repo_name, url, profile);
+
+    apply_if_inside(&url)
+}
+
+/// Assign a registered repository to another profile
+pub fn repo_set(name: &str, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
+
+    if store.profiles.get(profile).is_none() {
+        return Err(format!("profile '{}' not found", profile).into());
+    }
+
+    let mut repo = store
+        .repos
+        .find(name)
+        .cloned()
+        .ok_or_else(|| format!("repository '{}' not found", name))?;
+    repo.commit_by = profile.to_string();
+    let url = repo.url.clone();
+    let repo_name = repo.repo_name.clone();
+    store.repos.add(repo);
+    store.commit()?;
+    sync::refresh(&store.profiles, &store.repos)?;
+
+    println!("✓ Repository '{}' now uses profile '{}'", repo_name, profile);
+
+    apply_if_inside(&url)
+}
+
+/// Delete a repository configuration
+pub fn repo_delete(name: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
+    let repos: Vec<_> = store.repos.iter().map(|(url, repo)| (url.clone(), repo.clone())).collect();
+
+    if repos.is_empty() {
+        println!("No repositories configured.");
+        return Ok(());
+    }
+
+    let to_delete = if let Some(ref name) = name {
+        // Find by name or URL
+        store.repos.find(name).map(|repo| repo.url.clone())
+    } else {
+        // Interactive selection
+        let theme = ColorfulTheme::default();
+
+        println!();
+        println!("Select a repository to delete:");
+        println!();
+
+        let items: Vec<String> = repos
+            .iter()
+            .map(|(_, repo)| format!("{} ({})", repo.repo_name, repo.url))
+            .collect();
+
+        let selection = Select::with_theme(&theme)
+            .with_prompt("Choose repository")
+            .items(&items)
+            .default(0)
+            .interact()?;
+
+        Some(repos[selection].0.clone())
+    };
+
+    match to_delete {
+        Some(url) => {
+            let repo = store.repos.remove_by_url(&url);
+            if let Some(repo) = repo {
+                store.commit()?;
+                sync::refresh(&store.profiles, &store.repos)?;
+                println!("✓ Deleted repository '{}'", repo.repo_name);
+            }
+        }
+        None => {
+            if let Some(name) = name {
+                println!("Repository '{}' not found.", name);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// List all configured profiles
+pub fn profile_list() -> Result<(), Box<dyn std::error::Error>> {
+    let config_store = ConfigStore::load()?;
+
+    let profiles: Vec<_> = config_store.iter().collect();
+
+    if profiles.is_empty() {
+        println!("No profiles configured.");
+        println!();
+        println!("Create a profile by running: gamm pre-commit --repo <url>");
+        return Ok(());
+    }
+
+    println!();
+    println!("┌─────────────────────────────────────────────────────────────┐");
+    println!("│  Configured Profiles                                        │");
+    println!("└─────────────────────────────────────────────────────────────┘");
+    println!();
+
+    let home = dirs::home_dir();
+
+    for (name, config) in profiles {
+        print_profile_entry(&config_store, name, config, home.as_deref());
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Print a profile under its name; for a profile that extends another, both
+/// what it declares and the effective result
+fn print_profile_entry(config_store: &ConfigStore, name: &str, config: &store::GitConfig, home: Option<&Path>) {
+    println!("  {} ", name);
+    match &config.extends {
+        None => print_profile(config, "    ", false, home),
+        Some(base) => {
+            println!("    Extends:  {}", base);
+            println!("    Declared:");
+            print_profile(config, "      ", true, home);
+            match config_store.effective(name) {
+                Ok(effective) => {
+                    println!("    Effective:");
+                    print_profile(&effective, "      ", false, home);
+                }
+                Err(e) => println!("    Effective: unavailable ({})", e),
+            }
+        }
+    }
+}
+
+/// Print the fields of a profile. With `declared_only`, fields the profile
+/// leaves unset (and would inherit) are skipped.
+fn print_profile(config: &store::GitConfig, indent: &str, declared_only: bool, home: Option<&Path>) {
+    let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+
+    if !declared_only || !config.user.name.is_empty() {
+        println!("{}Name:     {}", indent, config.user.name);
+    }
+    if !declared_only || !config.user.email.is_empty() {
+        println!("{}Email:    {}", indent, config.user.email);
+    }
+    if !declared_only || config.commit.gpgsign.is_some() {
+        println!("{}GPG Sign: {}", indent, yes_no(config.commit.gpgsign.unwrap_or(false)));
+    }
+    if !declared_only || config.tag.gpgsign.is_some() {
+        println!("{}Tag Sign: {}", indent, yes_no(config.tag.gpgsign.unwrap_or(false)));
+    }
+    if let Some(signoff) = &config.user.signoff {
+        println!("{}Sign-off: {}", indent, signoff);
+    }
+    if !config.trailers.is_empty() {
+        println!("{}Trailers:", indent);
+        for trailer in &config.trailers {
+            println!("{}  {}: {}", indent, trailer.key, trailer.value);
+        }
+    }
+    if !config.extra.is_empty() {
+        println!("{}Extra:", indent);
+        for (key, values) in &config.extra {
+            for value in values {
+                println!("{}  {} = {}", indent, key, value);
+            }
+        }
+    }
+    if let Some(key) = &config.ssh_key {
+        let missing = !store::expand_home(key, home).is_file();
+        println!("{}SSH Key:  {}{}", indent, key, if missing { " (not found)" } else { "" });
+    }
+    if !config.signing.is_empty() {
+        let signing = &config.signing;
+        println!("{}Signing:", indent);
+        println!("{}  Format:  {}", indent, signing.format.unwrap_or_default());
+        if let Some(key) = &signing.key {
+            println!("{}  Key:     {}", indent, key);
+        }
+        if let Some(program) = &signing.program {
+            println!("{}  Program: {}", indent, program);
+        }
+        if let Some(program) = &signing.ssh_program {
+            println!("{}  SSH program: {}", indent, program);
+        }
+    }
+    if !config.urls.is_empty() {
+        println!("{}URL Rewrites:", indent);
+        let nested = format!("{}  ", indent);
+        for url in &config.urls {
+            print_rewrite(url, &nested);
+        }
+    }
+}
+
+/// Profile fields that can be given on the command line (`profile add` and
+/// `profile edit`). An empty value clears an optional field.
+#[derive(clap::Args, Debug, Default)]
+pub struct ProfileFields {
+    /// user.name
+    #[arg(long = "name", value_name = "NAME")]
+    pub user_name: Option<String>,
+    /// user.email
+    #[arg(long)]
+    pub email: Option<String>,
+    /// Sign commits ('--gpgsign false' turns it off)
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_name = "BOOL")]
+    pub gpgsign: Option<bool>,
+    /// Sign tags ('--tag-gpgsign false' turns it off)
+    #[arg(long, num_args = 0..=1, default_missing_value = "true", value_name = "BOOL")]
+    pub tag_gpgsign: Option<bool>,
+    /// Signature format: openpgp, ssh or x509
+    #[arg(long, value_name = "FORMAT")]
+    pub signing_format: Option<store::GpgFormat>,
+    /// user.signingkey
+    #[arg(long, value_name = "KEY")]
+    pub signing_key: Option<String>,
+    /// Signing program (gpg.ssh.program for the ssh format)
+    #[arg(long, value_name = "PATH")]
+    pub signing_program: Option<String>,
+    /// SSH private key used for this profile's remotes
+    #[arg(long, value_name = "PATH")]
+    pub ssh_key: Option<String>,
+    /// Signed-off-by trailer template (default '{name} <{email}>')
+    #[arg(long, num_args = 0..=1, default_missing_value = "{name} <{email}>", value_name = "TEMPLATE")]
+    pub signoff: Option<String>,
+    /// Base profile to inherit unset fields from
+    #[arg(long, value_name = "PROFILE")]
+    pub extends: Option<String>,
+    /// Scope override for this profile: global or local
+    #[arg(long)]
+    pub scope: Option<Scope>,
+}
+
+impl ProfileFields {
+    fn is_empty(&self) -> bool {
+        self.user_name.is_none()
+            && self.email.is_none()
+            && self.gpgsign.is_none()
+            && self.tag_gpgsign.is_none()
+            && self.signing_format.is_none()
+            && self.signing_key.is_none()
+            && self.signing_program.is_none()
+            && self.ssh_key.is_none()
+            && self.signoff.is_none()
+            && self.extends.is_none()
+            && self.scope.is_none()
+    }
+
+    /// Write the given fields into a profile, leaving the others alone
+    fn apply(self, config: &mut store::GitConfig) {
+        let optional = |value: String| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+
+        if let Some(name) = self.user_name {
+            config.user.name = name;
+        }
+        if let Some(email) = self.email {
+            config.user.email = email;
+        }
+        if let Some(gpgsign) = self.gpgsign {
+            config.commit.gpgsign = Some(gpgsign);
+        }
+        if let Some(gpgsign) = self.tag_gpgsign {
+            config.tag.gpgsign = Some(gpgsign);
+        }
+        if let Some(format) = self.signing_format {
+            config.signing.format = Some(format);
+        }
+        if let Some(key) = self.signing_key {
+            config.signing.key = optional(key);
+        }
+        if let Some(program) = self.signing_program {
+            match config.signing.format {
+                Some(store::GpgFormat::Ssh) => config.signing.ssh_program = optional(program),
+                _ => config.signing.program = optional(program),
+            }
+        }
+        if let Some(key) = self.ssh_key {
+            config.ssh_key = optional(key);
+        }
+        if let Some(signoff) = self.signoff {
+            config.user.signoff = optional(signoff);
+        }
+        if let Some(base) = self.extends {
+            config.extends = optional(base);
+        }
+        if let Some(scope) = self.scope {
+            config.scope = Some(scope);
+        }
+    }
+}
+
+/// Check that a profile resolves to a usable identity. A missing SSH key is
+/// an error when `strict`, otherwise only a warning.
+fn check_profile(config_store: &ConfigStore, name: &str, strict: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config_store.effective(name)?;
+    if config.user.name.is_empty() || config.user.email.is_empty() {
+        return Err(format!(
+            "profile '{}' needs a user.name and user.email (set --name/--email, or --extends a profile that has them)",
+            name
+        )
+        .into());
+    }
+
+    let home = dirs::home_dir();
+    if let Some(key) = &config.ssh_key
+        && !store::expand_home(key, home.as_deref()).is_file()
+    {
+        if strict {
+            return Err(format!("SSH key '{}' does not exist (pass --force to add the profile anyway)", key).into());
+        }
+        eprintln!("Warning: SSH key '{}' does not exist (yet)", key);
+    }
+
+    Ok(())
+}
+
+/// Create a profile from flags, or interactively if none are given. `force`
+/// accepts an SSH key that doesn't exist yet.
+pub fn profile_add(profile: &str, fields: ProfileFields, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
+    if store.profiles.get(profile).is_some() {
+        return Err(format!("profile '{}' already exists; change it with `gamm profile edit`", profile).into());
+    }
+
+    if fields.is_empty() {
+        add_config_interactive(&mut store.profiles, Some(profile.to_string()))?;
+        store.commit()?;
+    } else {
+        let mut config = store::GitConfig::default();
+        fields.apply(&mut config);
+        store.profiles.add(profile, config);
+        check_profile(&store.profiles, profile, !force)?;
+        store.commit()?;
+
+        println!("✓ Config profile '{}' created!", profile);
+    }
+
+    sync::refresh(&store.profiles, &store.repos)?;
+    Ok(())
+}
+
+/// Change a profile from flags, or interactively (pre-filled) if none are given
+pub fn profile_edit(profile: &str, fields: ProfileFields) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
+    let config = store
+        .profiles
+        .get_mut(profile)
+        .ok_or_else(|| format!("profile '{}' not found", profile))?;
+
+    if fields.is_empty() {
+        println!();
+        println!("Editing profile '{}'", profile);
+        println!();
+        *config = prompt_profile(&ColorfulTheme::default(), config)?;
+    } else {
+        fields.apply(config);
+    }
+
+    check_profile(&store.profiles, profile, false)?;
+    store.commit()?;
+    sync::refresh(&store.profiles, &store.repos)?;
+
+    println!("✓ Updated profile '{}'", profile);
+    Ok(())
+}
+
+/// Show a single profile
+pub fn profile_show(profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let config_store = ConfigStore::load()?;
+    let config = config_store
+        .get(profile)
+        .ok_or_else(|| format!("profile '{}' not found", profile))?;
+
+    println!();
+    print_profile_entry(&config_store, profile, config, dirs::home_dir().as_deref());
+    println!();
+    Ok(())
+}
+
+/// Rename a profile and every repository, rule and profile referring to it
+pub fn profile_rename(old: &str, new: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if new.trim().is_empty() {
+        return Err("the new profile name can't be empty".into());
+    }
+
+    let mut store = Store::load()?;
+
+    let children = store.profiles.children(old);
+    store.profiles.rename(old, new)?;
+    let references = store.repos.rename_owner(old, new);
+
+    // Profiles and repos are written by one commit, so an interrupted rename
+    // is finished from the journal instead of leaving repos on the old name
+    store.commit()?;
+    identity::rename_profile(old, new)?;
+    sync::refresh(&store.profiles, &store.repos)?;
+
+    println!("✓ Renamed profile '{}' to '{}'", old, new);
+    if references > 0 {
+        println!("✓ Updated {} repository configuration(s) and rule(s)", references);
+    }
+    if !children.is_empty() {
+        println!("✓ Updated profiles extending it: {}", children.join(", "));
+    }
+    Ok(())
+}
+
+/// Set an extra git config key on a profile (`add` keeps existing values)
+pub fn profile_set(profile: &str, key: &str, value: &str, add: bool) -> Result<(), Box<dyn std::error::Error>> {
+    store::GitConfig::validate_extra_key(key)?;
+
+    let mut store = Store::load()?;
+    let config = store
+        .profiles
+        .get_mut(profile)
+        .ok_or_else(|| format!("profile '{}' not found", profile))?;
+
+    let values = config.extra.entry(key.to_string()).or_default();
+    if !add {
+        values.clear();
+    }
+    values.push(value.to_string());
+
+    store.commit()?;
+    sync::refresh(&store.profiles, &store.repos)?;
+
+    println!("✓ {} {} = {} on profile '{}'", if add { "Added" } else { "Set" }, key, value, profile);
+
+    Ok(())
+}
+
+/// Remove an extra git config key from a profile
+pub fn profile_unset(profile: &str, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
+    let config = store
+        .profiles
+        .get_mut(profile)
+        .ok_or_else(|| format!("profile '{}' not found", profile))?;
+
+    if config.extra.remove(key).is_none() {
+        println!("Profile '{}' does not set '{}'.", profile, key);
+        return Ok(());
+    }
+
+    store.commit()?;
+    sync::refresh(&store.profiles, &store.repos)?;
+
+    println!("✓ Unset {} on profile '{}'", key, profile);
+
+    Ok(())
+}
+
+/// Print a URL rewrite as git config and as what it does
+fn print_rewrite(url: &store::UrlConfig, indent: &str) {
+    println!("{}{} = {}", indent, url.key(), url.instead_of);
+    println!("{}  (URLs starting with '{}' use '{}' instead)", indent, url.instead_of, url.pattern);
+}
+
+/// List the URL rewrites of one profile, or of all profiles
+pub fn profile_url_list(profile: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let config_store = ConfigStore::load()?;
+
+    let mut profiles: Vec<_> = match profile {
+        Some(name) => {
+            let config = config_store
+                .get(name)
+                .ok_or_else(|| format!("profile '{}' not found", name))?;
+            vec![(name, config)]
+        }
+        None => config_store.iter().map(|(n, c)| (n.as_str(), c)).collect(),
+    };
+    profiles.retain(|(_, config)| !config.urls.is_empty());
+    profiles.sort_by_key(|(name, _)| *name);
+
+    if profiles.is_empty() {
+        println!("No URL rewrites configured.");
+        println!();
+        println!("Add one with: gamm profile url add <profile> git@github.com: --instead-of https://github.com/");
+        return Ok(());
+    }
+
+    for (name, config) in profiles {
+        println!("  {}", name);
+        for url in &config.urls {
+            print_rewrite(url, "    ");
+        }
+    }
+
+    Ok(())
+}
+
+/// Add a URL rewrite to a profile
+pub fn profile_url_add(profile: &str, base: &str, instead_of: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
+    let config = store
+        .profiles
+        .get_mut(profile)
+        .ok_or_else(|| format!("profile '{}' not found", profile))?;
+
+    if base.trim().is_empty() || instead_of.trim().is_empty() {
+        return Err("both the base URL and --instead-of must be non-empty".into());
+    }
+
+    let url = store::UrlConfig {
+        pattern: base.to_string(),
+        instead_of: instead_of.to_string(),
+    };
+    if config.urls.contains(&url) {
+        println!("Profile '{}' already rewrites '{}' to '{}'.", profile, instead_of, base);
+        return Ok(());
+    }
+    config.urls.push(url);
+
+    store.commit()?;
+    sync::refresh(&store.profiles, &store.repos)?;
+
+    println!("✓ Profile '{}' now rewrites URLs starting with '{}' to '{}'", profile, instead_of, base);
+
+    Ok(())
+}
+
+/// Remove URL rewrites with the given base (and prefix) from a profile
+pub fn profile_url_remove(profile: &str, base: &str, instead_of: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
+    let config = store
+        .profiles
+        .get_mut(profile)
+        .ok_or_else(|| format!("profile '{}' not found", profile))?;
+
+    let (removed, kept): (Vec<_>, Vec<_>) = config
+        .urls
+        .drain(..)
+        .partition(|url| url.pattern == base && instead_of.is_none_or(|i| url.instead_of == i));
+    config.urls = kept;
+
+    if removed.is_empty() {
+        println!("No matching URL rewrite in profile '{}'.", profile);
+        return Ok(());
+    }
+
+    store.commit()?;
+    sync::refresh(&store.profiles, &store.repos)?;
+
+    println!("✓ Removed {} URL rewrite(s) from profile '{}'", removed.len(), profile);
+
+    // Remove them from git config where gamm applied them, unless a
+    // profile (this one through inheritance, or another) still defines them
+    let defined: Vec<_> = store
+        .profiles
+        .iter()
+        .filter_map(|(name, _)| store.profiles.effective_or_declared(name))
+        .flat_map(|config| config.urls)
+        .collect();
+    let mut scopes = vec![Scope::Global];
+    if git::in_work_tree() {
+        scopes.push(Scope::Local);
+    }
+    for url in removed.iter().filter(|url| !defined.contains(url)) {
+        let entry = Managed::Value(url.key(), url.instead_of.clone());
+        for &scope in &scopes {
+            if managed::forget(scope, &entry)? {
+                println!("  {} ({} config)", entry, scope);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete a profile configuration (also removes related repositories and
+/// rules)
+pub fn profile_delete(name: Option<String>, detach: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::load()?;
+
+    let profiles: Vec<_> = store.profiles.iter().map(|(n, _)| n.clone()).collect();
+
+    if profiles.is_empty() {
+        println!("No profiles configured.");
+        return Ok(());
+    }
+
+    let to_delete = if let Some(ref name) = name {
         // Find by name
         if profiles.contains(name) {
             Some(name.clone())
@@ -571,12 +1565,13 @@ pub fn profile_delete(name: Option<String>) -> Result<(), Box<dyn std::error::Er
         println!("Select a profile to delete:");
         println!();
 
-        let items: Vec<String> = config_store
+        let items: Vec<String> = store
+            .profiles
             .iter()
             .map(|(name, config)| format!("{} - {} <{}>", name, config.user.name, config.user.email))
             .collect();
 
-        let profile_names: Vec<_> = config_store.iter().map(|(n, _)| n.clone()).collect();
+        let profile_names: Vec<_> = store.profiles.iter().map(|(n, _)| n.clone()).collect();
 
         let selection = Select::with_theme(&theme)
             .with_prompt("Choose profile")
@@ -589,29 +1584,39 @@ pub fn profile_delete(name: Option<String>) -> Result<(), Box<dyn std::error::Er
 
     match to_delete {
         Some(profile_name) => {
-            // Find and remove all repos associated with this profile
-            let related_repos: Vec<String> = repo_store
-                .find_by_owner(&profile_name)
-                .iter()
-                .map(|r| r.url.clone())
-                .collect();
-
-            let removed_repos_count = related_repos.len();
+            // Profiles extending this one would be left with a broken chain
+            let children = store.profiles.children(&profile_name);
+            if !children.is_empty() {
+                if !detach {
+                    return Err(format!(
+                        "profile '{}' is extended by {}; use --detach to copy its values into them first",
+                        profile_name,
+                        children.join(", ")
+                    )
+                    .into());
+                }
 
-            // Remove all related repos
-            for url in related_repos {
-                repo_store.remove_by_url(&url);
+                let base = store.profiles.get(&profile_name).cloned().unwrap_or_default();
+                for child in &children {
+                    if let Some(config) = store.profiles.get_mut(child) {
+                        *config = config.merged_over(&base);
+                    }
+                    println!("✓ Detached '{}' from '{}'", child, profile_name);
+                }
             }
 
+            // Remove the repos and rules using this profile
+            let removed_repos_count = store.repos.remove_owner(&profile_name);
+
             // Remove the profile
-            let removed = config_store.remove(&profile_name);
+            let removed = store.profiles.remove(&profile_name);
             if removed.is_some() {
-                config_store.save()?;
-                repo_store.save()?;
+                store.commit()?;
+                sync::refresh(&store.profiles, &store.repos)?;
 
                 println!("✓ Deleted profile '{}'", profile_name);
                 if removed_repos_count > 0 {
-                    println!("✓ Cleaned up {} related repository configuration(s)", removed_repos_count);
+                    println!("✓ Cleaned up {} related repository configuration(s) and rule(s)", removed_repos_count);
                 }
             }
         }
@@ -625,88 +1630,562 @@ pub fn profile_delete(name: Option<String>) -> Result<(), Box<dyn std::error::Er
     Ok(())
 }
 
-pub fn cleanup() -> Result<(), Box<dyn std::error::Error>> {
-    let githooks_dir = get_githooks_dir();
-    let pre_commit_path = githooks_dir.join("pre-commit");
+/// Compile profiles into gitconfig fragments and includeIf entries
+pub fn sync() -> Result<(), Box<dyn std::error::Error>> {
+    let config_store = ConfigStore::load()?;
+    let repo_store = RepoStore::load()?;
 
-    if !pre_commit_path.exists() {
-        println!("No pre-commit hook found at: {}", pre_commit_path.display());
-        return Ok(());
+    let summary = sync::write(&config_store, &repo_store)?;
+
+    println!("✓ Wrote {} profile fragment(s)", summary.profiles);
+    println!(
+        "✓ Wrote {} includeIf entr{} to {}",
+        summary.includes,
+        if summary.includes == 1 { "y" } else { "ies" },
+        summary.gitconfig.display()
+    );
+    for name in &summary.skipped {
+        println!("  Skipped '{}': its profile no longer exists or can't be resolved", name);
     }
+    println!();
+    println!("Git now picks the identity itself (requires git 2.36+ for hasconfig).");
+    println!("The block is refreshed automatically when repos or profiles change.");
 
-    let content = fs::read_to_string(&pre_commit_path)?;
-    
-    if !content.contains(GAM_MARKER_START) {
-        println!("No gamm config found in: {}", pre_commit_path.display());
-        return Ok(());
+    Ok(())
+}
+
+/// Install the `git` shim
+pub fn shim_install() -> Result<(), Box<dyn std::error::Error>> {
+    refuse_config_dir_flag("the git shim")?;
+    let dir = shim::install()?;
+
+    println!("✓ Installed git shim: {}", dir.join("git").display());
+    println!();
+    println!("Put it ahead of the real git on your PATH, e.g. in your shell profile:");
+    println!("  export PATH=\"{}:$PATH\"", dir.display());
+    println!();
+    println!("Commits then use the repository's profile directly, without aborting.");
+
+    Ok(())
+}
+
+/// Remove the `git` shim
+pub fn shim_uninstall() -> Result<(), Box<dyn std::error::Error>> {
+    match shim::uninstall()? {
+        Some(link) => {
+            println!("Removed git shim: {}", link.display());
+            println!("Remember to drop its directory from your PATH.");
+        }
+        None => println!("No git shim installed."),
     }
 
-    // Remove the gam section (including markers and surrounding newlines)
-    let mut new_content = String::new();
-    let mut in_gam_section = false;
-    
-    for line in content.lines() {
-        if line.trim() == GAM_MARKER_START {
-            in_gam_section = true;
-            continue;
+    Ok(())
+}
+
+/// Show how the current repository resolves and whether git's configuration
+/// matches its profile. Exits with status 1 on drift.
+pub fn status() -> Result<(), Box<dyn std::error::Error>> {
+    if !git::in_work_tree() {
+        return Err("not inside a git repository".into());
+    }
+
+    let repo_store = RepoStore::load()?;
+    let config_store = ConfigStore::load()?;
+    let settings = Settings::load()?;
+    let pinned = git::config_get(Scope::Local, git::REMOTE_PIN_KEY);
+
+    println!();
+    if let Some(path) = git::toplevel() {
+        println!("  Repository: {}", path.display());
+    }
+    let remotes = repo::order_remotes(git::remotes(), &settings.remote_priority);
+    if remotes.is_empty() {
+        println!("  Remotes:    (none)");
+    }
+    for (i, remote) in remotes.iter().enumerate() {
+        let label = if i == 0 { "Remotes:" } else { "" };
+        let pin = if pinned.as_deref() == Some(remote.name.as_str()) { " [pinned]" } else { "" };
+        println!("  {:<11} {}{} {}", label, remote.name, pin, remote.url);
+    }
+
+    let found = match resolve_current(&repo_store, &settings) {
+        Ok(Some(found)) => found,
+        Ok(None) => {
+            println!();
+            println!("  No profile matches this repository.");
+            println!("  Register it with: gamm repo add --profile <name>");
+            println!();
+            return Ok(());
         }
-        if line.trim() == GAM_MARKER_END {
-            in_gam_section = false;
-            continue;
+        Err(conflict) => return Err(conflict.into()),
+    };
+
+    let owner = found.found.commit_by;
+    let mut via = found.found.source.to_string();
+    if let Some(remote) = &found.remote {
+        via.push_str(&format!(", remote {}", remote.name));
+    }
+    println!("  Profile:    {} ({})", owner, via);
+
+    let config = config_store.effective(owner)?;
+    let scope = settings.scope_for(&config);
+    println!("  Scope:      {}", scope);
+    println!();
+
+    let statuses = status::check(&config, scope);
+    let width = statuses.iter().map(|s| s.key.len()).max().unwrap_or(0);
+    let describe = |actual: &[(String, String)]| {
+        if actual.is_empty() {
+            "(not set)".to_string()
+        } else {
+            actual
+                .iter()
+                .map(|(scope, value)| format!("{} ({})", value, scope))
+                .collect::<Vec<_>>()
+                .join(", ")
         }
-        if !in_gam_section {
-            new_content.push_str(line);
-            new_content.push('\n');
+    };
+
+    for key in &statuses {
+        match key.state {
+            status::State::Matches => {
+                println!("  ✓ {:<width$}  {}", key.key, describe(&key.actual), width = width);
+            }
+            status::State::Differs => {
+                println!(
+                    "  ✗ {:<width$}  {}, expected {}",
+                    key.key,
+                    describe(&key.actual),
+                    key.expected.join(", "),
+                    width = width
+                );
+            }
+            status::State::Unexpected => {
+                println!(
+                    "  ✗ {:<width$}  {}, should not be set in {} scope",
+                    key.key,
+                    describe(&key.actual),
+                    scope,
+                    width = width
+                );
+            }
         }
     }
 
-    // Clean up extra blank lines
-    let new_content = new_content.trim_end().to_string();
-    
-    // Check if remaining content is just shebang/empty
-    let is_empty = new_content.lines()
-        .filter(|l| !l.trim().is_empty() && !l.starts_with("#!") && !l.starts_with("set -e"))
-        .count() == 0;
+    let drifted = statuses.iter().filter(|s| s.drifted()).count();
+    println!();
+    if drifted == 0 {
+        println!("✓ Git uses profile '{}'", owner);
+        println!();
+        Ok(())
+    } else {
+        println!("✗ {} key(s) differ from profile '{}'", drifted, owner);
+        println!();
+        std::process::exit(1);
+    }
+}
+
+/// Check the installation and configuration, optionally applying safe repairs.
+/// Exits with status 1 if a check still fails.
+pub fn doctor(fix: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let checks = doctor::run();
 
-    if is_empty {
-        // Remove the file entirely if only gam was in it
-        fs::remove_file(&pre_commit_path)?;
-        println!("Removed pre-commit hook: {}", pre_commit_path.display());
-        
-        // Remove the directory if it's empty
-        if githooks_dir.read_dir()?.next().is_none() {
-            fs::remove_dir(&githooks_dir)?;
-            println!("Removed empty directory: {}", githooks_dir.display());
+    println!();
+    let mut fixable = 0;
+    let mut failed = 0;
+    let mut warned = 0;
+    for check in &checks {
+        let mark = match check.level {
+            doctor::Level::Pass => "✓",
+            doctor::Level::Warn => "!",
+            doctor::Level::Fail => "✗",
+        };
+        println!("  {} {}", mark, check.message);
+
+        // A repaired check no longer counts against the result
+        let mut level = check.level;
+        match (&check.fix, fix) {
+            (Some(repair), true) => match repair.apply() {
+                Ok(()) => {
+                    println!("      Fixed");
+                    level = doctor::Level::Pass;
+                }
+                Err(e) => println!("      Fix failed: {}", e),
+            },
+            (Some(_), false) => fixable += 1,
+            (None, _) => {}
+        }
+        if level != doctor::Level::Pass
+            && let Some(hint) = &check.hint
+        {
+            println!("      → {}", hint);
+        }
+
+        match level {
+            doctor::Level::Fail => failed += 1,
+            doctor::Level::Warn => warned += 1,
+            doctor::Level::Pass => {}
         }
+    }
+
+    println!();
+    println!("{} failed, {} warning(s)", failed, warned);
+    if fixable > 0 {
+        println!("{} can be repaired with `gamm doctor --fix`", fixable);
+    }
+    println!();
+
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// List the remotes of the current repository in the order they are tried
+pub fn remote_list() -> Result<(), Box<dyn std::error::Error>> {
+    if !git::in_work_tree() {
+        return Err("not inside a git repository".into());
+    }
+
+    let repo_store = RepoStore::load()?;
+    let settings = Settings::load()?;
+    let pinned = git::config_get(Scope::Local, git::REMOTE_PIN_KEY);
+
+    let remotes = repo::order_remotes(git::remotes(), &settings.remote_priority);
+    if remotes.is_empty() {
+        println!("This repository has no remotes.");
+        return Ok(());
+    }
+
+    for remote in &remotes {
+        let owner = repo_store
+            .lookup(&remote.url)
+            .map(|m| format!("{} ({})", m.commit_by, m.source))
+            .unwrap_or_else(|| "(no match)".to_string());
+        let pin = if pinned.as_deref() == Some(remote.name.as_str()) { " [pinned]" } else { "" };
+        println!("  {}{} {}", remote.name, pin, remote.url);
+        println!("    Profile: {}", owner);
+    }
+
+    println!();
+    let path = git::toplevel();
+    let ordered = git::ordered_remotes(&settings.remote_priority);
+    match repo_store.resolve_remotes(&ordered, path.as_deref(), &settings.remote_priority) {
+        Ok(Some(RemoteMatch { remote: Some(remote), found })) => {
+            println!("Resolves to '{}' via remote {}", found.commit_by, remote.name)
+        }
+        Ok(Some(RemoteMatch { remote: None, found })) => {
+            println!("Resolves to '{}' via {}", found.commit_by, found.source)
+        }
+        Ok(None) => println!("No profile matches this repository."),
+        Err(conflict) => println!("{}", conflict),
+    }
+
+    Ok(())
+}
+
+/// Pin the remote that decides the profile of the current repository
+pub fn remote_pin(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if git::remote_url(name).is_none() {
+        return Err(format!("remote '{}' not found in this repository", name).into());
+    }
+
+    git::config_set(Scope::Local, git::REMOTE_PIN_KEY, name)?;
+    println!("✓ Remote '{}' now decides the profile of this repository", name);
+
+    Ok(())
+}
+
+/// Remove the pinned remote of the current repository
+pub fn remote_unpin() -> Result<(), Box<dyn std::error::Error>> {
+    if !git::in_work_tree() {
+        return Err("not inside a git repository".into());
+    }
+
+    match git::config_get(Scope::Local, git::REMOTE_PIN_KEY) {
+        Some(name) => {
+            git::config_unset_all(Scope::Local, git::REMOTE_PIN_KEY)?;
+            println!("✓ Unpinned remote '{}'", name);
+        }
+        None => println!("No remote is pinned in this repository."),
+    }
+
+    Ok(())
+}
+
+/// List all store-wide settings
+pub fn config_list() -> Result<(), Box<dyn std::error::Error>> {
+    let settings = Settings::load()?;
+
+    for key in Settings::KEYS {
+        println!("{} = {}", key, settings.get(key).unwrap_or_default());
+    }
+
+    Ok(())
+}
+
+/// Print a single store-wide setting
+pub fn config_get(key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = Settings::load()?;
+
+    match settings.get(key) {
+        Some(value) => println!("{}", value),
+        None => return Err(format!("unknown setting '{}'", key).into()),
+    }
+
+    Ok(())
+}
+
+/// Update a store-wide setting
+pub fn config_set(key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut settings = Settings::load()?;
+    settings.set(key, value)?;
+    settings.save()?;
+
+    println!("✓ {} = {}", key, value);
+
+    Ok(())
+}
+
+/// `path` with `.bak` appended, or `.<n>.bak` when that is taken, so an
+/// earlier backup is never overwritten
+fn unused_backup(path: &Path) -> PathBuf {
+    let with = |suffix: String| {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+    std::iter::once(with(".bak".into()))
+        .chain((1..).map(|n| with(format!(".{}.bak", n))))
+        .find(|backup| fs::symlink_metadata(backup).is_err())
+        .expect("some backup name is free")
+}
+
+/// Move profiles, repositories and rules to another file format, keeping
+/// the previous files as `.bak`
+pub fn config_convert(to: Format) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = crate::storage::require_config_dir()?;
+    let from = Format::detect_in(&dir);
+    if from == to {
+        println!("Profiles, repositories and rules are already stored as {}", to);
+        return Ok(());
+    }
+
+    Store::load_from(&dir)?.commit_as(to)?;
+
+    let files = |format| match format {
+        Format::Json => vec![dir.join(ConfigStore::FILE), dir.join(RepoStore::FILE)],
+        Format::Toml => vec![dir.join(document::FILE)],
+    };
+    for path in files(from).into_iter().filter(|p| p.exists()) {
+        let backup = unused_backup(&path);
+        fs::rename(&path, &backup)?;
+        println!("  Moved {} to {}", path.display(), backup.display());
+    }
+
+    for path in files(to) {
+        println!("✓ Wrote {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Switch to repository-local identity and remove the identity gamm
+/// previously wrote to the global git config
+pub fn migrate() -> Result<(), Box<dyn std::error::Error>> {
+    let config_store = ConfigStore::load()?;
+    let mut settings = Settings::load()?;
+
+    if settings.scope != Scope::Local {
+        settings.scope = Scope::Local;
+        settings.save()?;
+    }
+    println!("✓ Profiles are now applied to each repository's .git/config");
+
+    let pinned: Vec<_> = config_store
+        .iter()
+        .filter(|(_, c)| c.scope == Some(Scope::Global))
+        .map(|(n, _)| n.clone())
+        .collect();
+    if !pinned.is_empty() {
+        println!(
+            "  Profiles still pinned to the global scope: {}",
+            pinned.join(", ")
+        );
+    }
+
+    // Only touch global values that gamm wrote: what it recorded in
+    // gamm.managed and still holds a profile's value, or, without records,
+    // the identity of the profile whose email is set globally
+    let mut names: Vec<&String> = config_store.iter().map(|(n, _)| n).collect();
+    names.sort();
+    let profiles: Vec<store::GitConfig> = names.iter().filter_map(|n| config_store.effective(n).ok()).collect();
+
+    let recorded = managed::read(Scope::Global);
+    let mut stale: Vec<Managed> = Vec::new();
+    if !recorded.is_empty() {
+        let entries: Vec<_> = profiles.iter().map(|c| c.entries()).collect();
+        stale = managed::unchanged(&recorded, |key| git::config_get_all(Scope::Global, key), &entries);
     } else {
-        // Write back the file without gam section
-        fs::write(&pre_commit_path, format!("{}\n", new_content))?;
-        println!("Removed gamm config from: {}", pre_commit_path.display());
+        let global_email = git::config_get(Scope::Global, "user.email");
+        if let Some(config) = profiles
+            .iter()
+            .find(|c| global_email.as_deref() == Some(c.user.email.as_str()))
+        {
+            stale.push(Managed::Key("user.email".into()));
+            if git::config_get(Scope::Global, "user.name").as_deref() == Some(config.user.name.as_str()) {
+                stale.push(Managed::Key("user.name".into()));
+            }
+            let gpgsign = config.commit.gpgsign.unwrap_or(false).to_string();
+            if git::config_get(Scope::Global, "commit.gpgsign").as_deref() == Some(gpgsign.as_str()) {
+                stale.push(Managed::Key("commit.gpgsign".into()));
+            }
+        }
+        for config in &profiles {
+            for url in &config.urls {
+                let key = format!("url.{}.insteadOf", url.pattern);
+                let values = git::config_get_all(Scope::Global, &key);
+                let entry = Managed::Value(key, url.instead_of.clone());
+                if !values.is_empty() && values.iter().all(|v| v == &url.instead_of) && !stale.contains(&entry) {
+                    stale.push(entry);
+                }
+            }
+        }
     }
 
-    // Clean up config files
-    if let Some(config_path) = store::ConfigStore::config_path() {
-        if config_path.exists() {
-            fs::remove_file(&config_path)?;
-            println!("Removed config: {}", config_path.display());
+    if stale.is_empty() {
+        println!("✓ No gamm-written identity found in the global git config");
+        return Ok(());
+    }
+
+    println!();
+    println!("The global git config still holds values written by gamm:");
+    for entry in &stale {
+        match entry {
+            Managed::Key(key) => println!("  {} = {}", key, git::config_get_all(Scope::Global, key).join(", ")),
+            Managed::Value(..) => println!("  {}", entry),
         }
     }
+    println!();
 
-    if let Some(repos_path) = crate::repo::RepoStore::repos_path() {
-        if repos_path.exists() {
-            fs::remove_file(&repos_path)?;
-            println!("Removed repos: {}", repos_path.display());
+    let remove = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Remove them from the global git config?")
+        .default(true)
+        .interact()?;
+
+    if remove {
+        for entry in &stale {
+            if recorded.is_empty() {
+                entry.unset(Scope::Global)?;
+            } else {
+                managed::forget(Scope::Global, entry)?;
+            }
         }
+        println!("✓ Removed {} global value(s)", stale.len());
     }
 
-    // Remove config directory if empty
-    if let Some(config_dir) = store::ConfigStore::config_dir() {
-        if config_dir.exists() && config_dir.read_dir()?.next().is_none() {
-            fs::remove_dir(&config_dir)?;
-            println!("Removed empty directory: {}", config_dir.display());
+    Ok(())
+}
+
+/// Remove the gamm section from the pre-commit hook
+fn remove_hooks() -> Result<(), Box<dyn std::error::Error>> {
+    for (name, _) in hooks::HOOKS {
+        let path = hooks::hook_path(name);
+        match hooks::remove(name)? {
+            hooks::Removed::Missing => println!("No {} hook found at: {}", name, path.display()),
+            hooks::Removed::NotManaged => println!("No gamm config found in: {}", path.display()),
+            hooks::Removed::Stripped => println!("Removed gamm config from: {}", path.display()),
+            hooks::Removed::Deleted => println!("Removed {} hook: {}", name, path.display()),
+        }
+    }
+
+    // Remove the directory if it's empty
+    let githooks_dir = hooks::githooks_dir();
+    if githooks_dir.exists() && githooks_dir.read_dir()?.next().is_none() {
+        fs::remove_dir(&githooks_dir)?;
+        println!("Removed empty directory: {}", githooks_dir.display());
+
+        // Let git run each repository's own hooks again
+        if hooks::global_hooks_path() == hooks::HooksPath::Ours {
+            git::config_unset_all(Scope::Global, "core.hooksPath")?;
+            println!("Unset core.hooksPath");
         }
     }
 
     Ok(())
 }
 
+pub fn cleanup() -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(snapshot) = identity::restore_pending()? {
+        println!("Restored global identity saved before switching to '{}'", snapshot.profile);
+    }
+    remove_hooks()?;
+    sync::remove()?;
+    if let Some(link) = shim::uninstall()? {
+        println!("Removed git shim: {}", link.display());
+    }
+
+    // Unset what gamm applied globally and nobody changed since, and its
+    // records, before the profiles that tell which values gamm wrote are gone
+    let recorded = managed::read(Scope::Global);
+    if !recorded.is_empty() {
+        let entries: Vec<_> = ConfigStore::load()
+            .map(|store| store.iter().filter_map(|(name, _)| store.effective(name).ok()).map(|c| c.entries()).collect())
+            .unwrap_or_default();
+        for entry in managed::unchanged(&recorded, |key| git::config_get_all(Scope::Global, key), &entries) {
+            if entry.unset(Scope::Global)? {
+                println!("Unset global {}", entry);
+            }
+        }
+        git::config_unset_all(Scope::Global, managed::KEY)?;
+        println!("Unset {}", managed::KEY);
+    }
+
+    // Clean up config files
+    if let Some(config_path) = store::ConfigStore::config_path()
+        && config_path.exists()
+    {
+        fs::remove_file(&config_path)?;
+        println!("Removed config: {}", config_path.display());
+    }
+
+    if let Some(repos_path) = crate::repo::RepoStore::repos_path()
+        && repos_path.exists()
+    {
+        fs::remove_file(&repos_path)?;
+        println!("Removed repos: {}", repos_path.display());
+    }
+
+    if let Some(toml_path) = document::path()
+        && toml_path.exists()
+    {
+        fs::remove_file(&toml_path)?;
+        println!("Removed config: {}", toml_path.display());
+    }
+
+    if let Some(settings_path) = Settings::settings_path()
+        && settings_path.exists()
+    {
+        fs::remove_file(&settings_path)?;
+        println!("Removed settings: {}", settings_path.display());
+    }
+
+    // The lock is held by this process until it exits; removing the file
+    // doesn't release it
+    if let Some(lock_path) = crate::storage::lock_path()
+        && lock_path.exists()
+    {
+        fs::remove_file(&lock_path)?;
+    }
+
+    // Remove config directory if empty
+    if let Some(config_dir) = crate::storage::config_dir()
+        && config_dir.exists()
+        && config_dir.read_dir()?.next().is_none()
+    {
+        fs::remove_dir(&config_dir)?;
+        println!("Removed empty directory: {}", config_dir.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,336 @@
+/*
+ * database.rs
+ * Profiles, repositories and rules loaded and saved as one unit.
+ *
+ * Repositories, rules and directory rules name their profile in
+ * `commit_by`. Saving the two stores separately could leave them pointing
+ * at a profile that no longer exists (or, after a crash, save only one
+ * half of a change). `Store` owns both collections and commits them
+ * together: config.json and repos.json through a journal
+ * (`storage::write_all_atomic`), gamm.toml in a single write.
+ *
+ * A commit is refused when it would leave an entry without its profile.
+ * Entries that were already orphaned when the store was loaded (files
+ * edited by hand, or written by older versions) are reported by `load`
+ * and `gamm doctor`, but don't block unrelated changes.
+ */
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::document::{self, Format};
+use crate::repo::{Repo, RepoStore};
+use crate::storage;
+use crate::store::ConfigStore;
+
+/// An entry whose profile doesn't exist
+#[derive(Debug, Clone, PartialEq)]
+pub enum Orphan {
+    Repo { name: String, url: String, profile: String },
+    Rule { pattern: String, profile: String },
+    DirRule { pattern: String, profile: String },
+}
+
+impl Orphan {
+    /// Commands that fix it
+    pub fn fix(&self) -> String {
+        match self {
+            Orphan::Repo { url, .. } => format!("gamm repo set {} --profile <profile>, or gamm repo delete {}", url, url),
+            Orphan::Rule { pattern, .. } => {
+                format!("gamm repo rule add '{}' <profile>, or gamm repo rule remove '{}'", pattern, pattern)
+            }
+            Orphan::DirRule { pattern, .. } => {
+                format!("gamm repo dir add '{}' <profile>, or gamm repo dir remove '{}'", pattern, pattern)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Orphan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Orphan::Repo { name, profile, .. } => write!(f, "repository '{}' uses missing profile '{}'", name, profile),
+            Orphan::Rule { pattern, profile } => write!(f, "rule {} uses missing profile '{}'", pattern, profile),
+            Orphan::DirRule { pattern, profile } => {
+                write!(f, "directory rule {} uses missing profile '{}'", pattern, profile)
+            }
+        }
+    }
+}
+
+/// Entries of `repo_store` whose profile is missing from `config_store`:
+/// repositories by name, then rules and directory rules by precedence
+pub fn orphans(config_store: &ConfigStore, repo_store: &RepoStore) -> Vec<Orphan> {
+    let missing = |profile: &str| config_store.get(profile).is_none();
+
+    let mut repos: Vec<_> = repo_store.iter().map(|(_, r)| r).filter(|r| missing(&r.commit_by)).collect();
+    repos.sort_by(|a, b| a.repo_name.cmp(&b.repo_name));
+
+    let repos = repos.into_iter().map(|r| Orphan::Repo {
+        name: r.repo_name.clone(),
+        url: r.url.clone(),
+        profile: r.commit_by.clone(),
+    });
+    let rules = repo_store
+        .rules_by_precedence()
+        .into_iter()
+        .filter(|r| missing(&r.commit_by))
+        .map(|r| Orphan::Rule {
+            pattern: r.pattern.clone(),
+            profile: r.commit_by.clone(),
+        });
+    let dir_rules = repo_store
+        .dir_rules_by_precedence()
+        .into_iter()
+        .filter(|r| missing(&r.commit_by))
+        .map(|r| Orphan::DirRule {
+            pattern: r.pattern.clone(),
+            profile: r.commit_by.clone(),
+        });
+
+    repos.chain(rules).chain(dir_rules).collect()
+}
+
+/// Two entries for the same repository with different profiles
+pub fn conflict(repo: &Repo, kept: &Repo) -> String {
+    format!(
+        "repository {} is listed for both '{}' and '{}' (as {})",
+        kept.url, kept.commit_by, repo.commit_by, repo.url
+    )
+}
+
+/// Profiles, repositories and rules of one config dir
+#[derive(Debug)]
+pub struct Store {
+    dir: PathBuf,
+    pub profiles: ConfigStore,
+    pub repos: RepoStore,
+    /// Orphans found on load, which commits don't have to fix
+    orphaned: Vec<Orphan>,
+}
+
+impl Store {
+    /// Load both stores from the config dir, reporting orphaned entries.
+    /// Commands that commit should hold `storage::lock` from before loading.
+    pub fn load() -> io::Result<Self> {
+        let store = Self::load_from(&storage::require_config_dir()?)?;
+        for orphan in &store.orphaned {
+            eprintln!("gamm: {} (see `gamm doctor`)", orphan);
+        }
+        for (repo, kept) in store.repos.conflicts() {
+            eprintln!("gamm: {} (see `gamm doctor`)", conflict(repo, kept));
+        }
+        Ok(store)
+    }
+
+    /// Load both stores from `dir`
+    pub fn load_from(dir: &Path) -> io::Result<Self> {
+        let profiles = ConfigStore::load_from(dir)?;
+        let repos = RepoStore::load_from(dir)?;
+        let orphaned = orphans(&profiles, &repos);
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            profiles,
+            repos,
+            orphaned,
+        })
+    }
+
+    /// Entries whose profile is missing
+    pub fn orphans(&self) -> Vec<Orphan> {
+        orphans(&self.profiles, &self.repos)
+    }
+
+    /// Save both stores in the format in use, as one change
+    pub fn commit(&self) -> io::Result<()> {
+        self.commit_as(Format::detect_in(&self.dir))
+    }
+
+    /// Save both stores as one change in `format`. Fails without writing
+    /// anything if the change leaves an entry without its profile.
+    pub fn commit_as(&self, format: Format) -> io::Result<()> {
+        let new: Vec<String> = self
+            .orphans()
+            .into_iter()
+            .filter(|o| !self.orphaned.contains(o))
+            .map(|o| o.to_string())
+            .collect();
+        if !new.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not saved: {}", new.join("; ")),
+            ));
+        }
+
+        // Saving would drop the entries re-keying couldn't merge
+        let conflicts: Vec<String> = self
+            .repos
+            .conflicts()
+            .into_iter()
+            .map(|(repo, kept)| format!("{}; pick one with `gamm repo set {} --profile <profile>`", conflict(repo, kept), kept.url))
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not saved: {}", conflicts.join("; ")),
+            ));
+        }
+
+        std::fs::create_dir_all(&self.dir)?;
+        match format {
+            Format::Json => {
+                // Files of an older version were upgraded on load
+                ConfigStore::backup_in(&self.dir)?;
+                self.repos.backup_in(&self.dir, format)?;
+                storage::write_all_atomic(
+                    &self.dir,
+                    &[
+                        (self.dir.join(ConfigStore::FILE), self.profiles.to_json()?.into_bytes()),
+                        (self.dir.join(RepoStore::FILE), self.repos.to_json()?.into_bytes()),
+                    ],
+                )?;
+            }
+            Format::Toml => {
+                self.repos.backup_in(&self.dir, format)?;
+                let path = self.dir.join(document::FILE);
+                let mut doc = document::open(&path)?;
+                self.profiles.to_document(&mut doc, &path)?;
+                self.repos.to_document(&mut doc, &path)?;
+                storage::write_atomic(&path, doc.to_string().as_bytes())?;
+            }
+        }
+
+        for note in self.repos.notes() {
+            eprintln!("gamm: {}", note);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{DirRule, Rule};
+    use crate::store::{GitConfig, UserConfig};
+    use std::fs;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gamm_database_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn profile(email: &str) -> GitConfig {
+        GitConfig {
+            user: UserConfig {
+                name: "Jane".into(),
+                email: email.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn repo(name: &str, profile: &str) -> Repo {
+        Repo {
+            repo_name: name.into(),
+            url: format!("git@github.com:acme/{}.git", name),
+            commit_by: profile.into(),
+        }
+    }
+
+    #[test]
+    fn test_commit_keeps_references_intact() {
+        for format in [Format::Json, Format::Toml] {
+            let dir = temp_dir(&format.to_string());
+
+            let mut store = Store::load_from(&dir).unwrap();
+            store.profiles.add("work", profile("jane@acme.com"));
+            store.repos.add(repo("api", "work"));
+            store.commit_as(format).unwrap();
+
+            let mut store = Store::load_from(&dir).unwrap();
+            assert_eq!(Format::detect_in(&dir), format);
+            assert!(store.orphaned.is_empty());
+            assert_eq!(store.repos.find("api").unwrap().commit_by, "work");
+
+            // Removing a profile that is still in use is refused, and nothing is written
+            let before: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| fs::read(e.unwrap().path()).unwrap()).collect();
+            store.profiles.remove("work");
+            store.repos.add_rule(Rule {
+                pattern: "github.com/acme/*".into(),
+                commit_by: "home".into(),
+            });
+            let err = store.commit().unwrap_err().to_string();
+            assert!(err.contains("repository 'api' uses missing profile 'work'"), "{}", err);
+            assert!(err.contains("rule github.com/acme/* uses missing profile 'home'"), "{}", err);
+            let after: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| fs::read(e.unwrap().path()).unwrap()).collect();
+            assert_eq!(before, after);
+
+            assert_eq!(store.repos.remove_owner("work") + store.repos.remove_owner("home"), 2);
+            store.commit().unwrap();
+            let store = Store::load_from(&dir).unwrap();
+            assert!(store.profiles.get("work").is_none() && store.repos.iter().next().is_none());
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_load_never_writes() {
+        let dir = temp_dir("pure");
+        let config = r#"{"work": {"user": {"name": "Jane", "email": "jane@acme.com"}}}"#;
+        let repos = r#"{"repos": {"https://GitHub.com/acme/api": {"repo_name": "api", "url": "https://GitHub.com/acme/api", "commit_by": "work"}}}"#;
+        fs::write(dir.join(ConfigStore::FILE), config).unwrap();
+        fs::write(dir.join(RepoStore::FILE), repos).unwrap();
+
+        // Version 1 files and raw URL keys are upgraded in memory only
+        let store = Store::load_from(&dir).unwrap();
+        assert_eq!(store.repos.find("api").unwrap().commit_by, "work");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(fs::read_to_string(dir.join(ConfigStore::FILE)).unwrap(), config);
+        assert_eq!(fs::read_to_string(dir.join(RepoStore::FILE)).unwrap(), repos);
+
+        // The next commit writes them, keeping the originals
+        store.commit().unwrap();
+        let backup = |file: &str| fs::read_to_string(crate::schema::Schema::backup_path(&dir.join(file), 1)).unwrap();
+        assert_eq!(backup(ConfigStore::FILE), config);
+        assert_eq!(backup(RepoStore::FILE), repos);
+        assert!(fs::read_to_string(dir.join(RepoStore::FILE)).unwrap().contains("\"version\": 2"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_existing_orphans_are_reported_but_tolerated() {
+        let dir = temp_dir("orphans");
+        fs::write(dir.join(ConfigStore::FILE), r#"{"version": 2, "profiles": {}}"#).unwrap();
+        fs::write(
+            dir.join(RepoStore::FILE),
+            r#"{"version": 2, "repos": {}, "dir_rules": [{"pattern": "~/work/**", "commit_by": "gone"}]}"#,
+        )
+        .unwrap();
+
+        let mut store = Store::load_from(&dir).unwrap();
+        assert_eq!(
+            store.orphaned,
+            [Orphan::DirRule {
+                pattern: "~/work/**".into(),
+                profile: "gone".into()
+            }]
+        );
+
+        // Unrelated changes still save; new orphans don't
+        store.profiles.add("home", profile("jane@home.dev"));
+        store.commit().unwrap();
+        store.repos.add_dir_rule(DirRule {
+            pattern: "~/oss/**".into(),
+            commit_by: "other".into(),
+        });
+        assert!(store.commit().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,536 @@
+/*
+ * doctor.rs
+ * Diagnostics for `gamm doctor`.
+ *
+ * When gamm silently does nothing the cause is usually outside gamm: git
+ * doesn't run the hooks, a hook can't find the binary, or the stores point
+ * at profiles that no longer exist. Every check reports pass/warn/fail with
+ * a hint, and the repairs that can't lose anything come with a Fix that
+ * `gamm doctor --fix` applies. Without --fix doctor writes nothing: loading
+ * the stores never writes, except to finish an interrupted save, which
+ * doctor reports instead.
+ */
+
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::database;
+use crate::document::Format;
+use crate::git;
+use crate::hooks::{self, HookState, HooksPath};
+use crate::integration::{self, Integration};
+use crate::repo::RepoStore;
+use crate::settings::Settings;
+use crate::storage;
+use crate::store::{self, ConfigStore, GpgFormat, Scope};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// A repair that only adds or refreshes what gamm itself manages
+#[derive(Debug, PartialEq)]
+pub enum Fix {
+    /// Point the global core.hooksPath at ~/.githooks (only when unset)
+    SetHooksPath,
+    /// Install or refresh the gamm section of a hook
+    InstallHook(&'static str, &'static str),
+    /// chmod +x a hook
+    MakeExecutable(&'static str),
+    /// Finish an interrupted save of the stores in a config dir
+    Recover(PathBuf),
+}
+
+impl Fix {
+    pub fn apply(&self) -> io::Result<()> {
+        match self {
+            Fix::SetHooksPath => hooks::enable(),
+            Fix::InstallHook(name, body) => hooks::install(name, body).map(|_| ()),
+            Fix::MakeExecutable(name) => hooks::make_executable(name),
+            Fix::Recover(dir) => storage::recover(dir).map(|_| ()),
+        }
+    }
+}
+
+/// Outcome of one check
+#[derive(Debug)]
+pub struct Check {
+    pub level: Level,
+    pub message: String,
+    /// What to do about a warning or failure
+    pub hint: Option<String>,
+    pub fix: Option<Fix>,
+}
+
+impl Check {
+    fn pass(message: impl Into<String>) -> Self {
+        Self {
+            level: Level::Pass,
+            message: message.into(),
+            hint: None,
+            fix: None,
+        }
+    }
+
+    fn warn(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            level: Level::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+            fix: None,
+        }
+    }
+
+    fn fail(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            level: Level::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+            fix: None,
+        }
+    }
+
+    fn with_fix(mut self, fix: Fix) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+}
+
+/// Find an executable in a PATH-style list of directories
+fn find_in_path(name: &str, path: &OsStr) -> Option<PathBuf> {
+    std::env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| {
+            candidate
+                .metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+}
+
+/// Load a store, turning a parse error into a failed check
+fn check_store<T>(file: &str, load: impl Fn() -> io::Result<T>, checks: &mut Vec<Check>) -> Option<T> {
+    match load() {
+        Ok(store) => {
+            checks.push(Check::pass(format!("{} is valid", file)));
+            Some(store)
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                format!("{} can't be read: {}", file, e),
+                "fix the file by hand; gamm ignores it until then",
+            ));
+            None
+        }
+    }
+}
+
+/// Check that git runs the hooks in ~/.githooks, or that the current
+/// repository runs gamm through a hook manager instead
+fn check_hooks_path(global: &HooksPath, integration: Option<Integration>, checks: &mut Vec<Check>) {
+    let expected = hooks::githooks_dir();
+    let home = dirs::home_dir();
+
+    match (global, integration) {
+        (HooksPath::Ours, _) => checks.push(Check::pass(format!("core.hooksPath is {}", expected.display()))),
+        (HooksPath::Unset, Some(tool)) => checks.push(Check::pass(format!(
+            "core.hooksPath is not set; this repository runs gamm through {}",
+            tool
+        ))),
+        (HooksPath::Unset, None) => checks.push(
+            Check::fail(
+                "core.hooksPath is not set, so git doesn't run gamm's hooks",
+                format!(
+                    "git config --global core.hooksPath {}, or `gamm init --integration <tool>` in each repository",
+                    expected.display()
+                ),
+            )
+            .with_fix(Fix::SetHooksPath),
+        ),
+        (HooksPath::Other(path), _) => checks.push(Check::fail(
+            format!("core.hooksPath is {}, but gamm's hooks are in {}", path, expected.display()),
+            format!(
+                "point core.hooksPath at {}, or call `gamm hook run pre-commit` from your own hooks",
+                expected.display()
+            ),
+        )),
+    }
+
+    // A repository can override the global hooks directory (husky does)
+    if integration.is_none()
+        && git::in_work_tree()
+        && let Some(path) = git::config_get(Scope::Local, "core.hooksPath")
+        && store::expand_home(&path, home.as_deref()) != expected
+    {
+        checks.push(Check::warn(
+            format!("this repository sets core.hooksPath to {}, so gamm's hooks don't run here", path),
+            "call `gamm hook run pre-commit` from those hooks, or `git config --local --unset core.hooksPath`",
+        ));
+    }
+}
+
+/// Check every hook script gamm installs
+fn check_hook_scripts(checks: &mut Vec<Check>) {
+    for (name, body) in hooks::HOOKS {
+        let path = hooks::hook_path(name);
+        let check = match hooks::state(name, body) {
+            Ok(HookState::Current) => Check::pass(format!("{} hook is installed", name)),
+            Ok(HookState::Missing) => Check::fail(format!("{} hook is missing", name), "gamm init")
+                .with_fix(Fix::InstallHook(name, body)),
+            Ok(HookState::NotManaged) => {
+                Check::fail(format!("{} hook doesn't call gamm", name), "gamm init (keeps the rest of the script)")
+                    .with_fix(Fix::InstallHook(name, body))
+            }
+            Ok(HookState::Outdated) => Check::warn(format!("{} hook is out of date", name), "gamm init")
+                .with_fix(Fix::InstallHook(name, body)),
+            Ok(HookState::NotExecutable) => Check::fail(
+                format!("{} hook is not executable", name),
+                format!("chmod +x {}", path.display()),
+            )
+            .with_fix(Fix::MakeExecutable(name)),
+            Err(e) => Check::fail(format!("{} hook can't be read: {}", name, e), format!("check {}", path.display())),
+        };
+        checks.push(check);
+    }
+}
+
+/// Check that the hooks can find the gamm binary
+fn check_binary(checks: &mut Vec<Check>) {
+    let current = std::env::current_exe().ok();
+    let path = std::env::var_os("PATH").unwrap_or_default();
+
+    match find_in_path("gamm", &path) {
+        None => checks.push(Check::fail(
+            "gamm is not on PATH, so the hooks can't run it",
+            match current.as_ref().and_then(|c| c.parent()) {
+                Some(dir) => format!("add {} to PATH where git runs (GUI clients often use a shorter PATH)", dir.display()),
+                None => "add gamm's directory to PATH where git runs".to_string(),
+            },
+        )),
+        Some(found) => {
+            let same = current.as_ref().is_some_and(|c| same_file(c, &found));
+            if same {
+                checks.push(Check::pass(format!("gamm is on PATH ({})", found.display())));
+            } else {
+                checks.push(Check::warn(
+                    format!("the hooks run {}, not this gamm", found.display()),
+                    "remove the other copy or put this one first on PATH",
+                ));
+            }
+        }
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Check that every profile resolves and that repositories and rules only
+/// use existing profiles
+fn check_references(config_store: &ConfigStore, repo_store: &RepoStore) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let mut names: Vec<&String> = config_store.list().collect();
+    names.sort();
+    for name in names {
+        if let Err(e) = config_store.effective(name) {
+            checks.push(Check::fail(e, format!("gamm profile edit {} --extends <profile>", name)));
+        }
+    }
+
+    for orphan in database::orphans(config_store, repo_store) {
+        checks.push(Check::fail(orphan.to_string(), orphan.fix()));
+    }
+
+    for (repo, kept) in repo_store.conflicts() {
+        checks.push(Check::fail(
+            database::conflict(repo, kept),
+            format!("gamm repo set {} --profile <profile>, or gamm repo delete {}", kept.url, kept.url),
+        ));
+    }
+
+    if checks.is_empty() {
+        checks.push(Check::pass("profiles, repositories and rules are consistent"));
+    }
+    checks
+}
+
+/// Check that key files named by profiles exist, with what each profile
+/// inherits through `extends`
+fn check_keys(config_store: &ConfigStore, secret_key: impl Fn(&str, &str) -> Option<bool>) -> Vec<Check> {
+    let home = dirs::home_dir();
+    let exists = |path: &str| store::expand_home(path, home.as_deref()).is_file();
+    let mut checks = Vec::new();
+
+    let mut names: Vec<&String> = config_store.list().collect();
+    names.sort();
+    for name in names {
+        let config = match config_store.effective(name) {
+            Ok(config) => config,
+            Err(e) => {
+                checks.push(Check::fail(
+                    format!("profile '{}': keys not checked: {}", name, e),
+                    format!("gamm profile edit {} --extends <profile>", name),
+                ));
+                continue;
+            }
+        };
+        if let Some(key) = &config.ssh_key
+            && !exists(key)
+        {
+            checks.push(Check::fail(
+                format!("profile '{}': SSH key {} not found", name, key),
+                format!("gamm profile edit {} --ssh-key <path>", name),
+            ));
+        }
+
+        let Some(key) = &config.signing.key else {
+            continue;
+        };
+        let format = config.signing.format.unwrap_or_default();
+        match format {
+            // SSH signing keys are files unless given literally as `key::...`
+            GpgFormat::Ssh => {
+                if !key.starts_with("key::") && !exists(key) {
+                    checks.push(Check::fail(
+                        format!("profile '{}': signing key {} not found", name, key),
+                        format!("gamm profile edit {} --signing-key <path>", name),
+                    ));
+                }
+            }
+            // OpenPGP and X.509 keys live in the program's keyring
+            GpgFormat::Openpgp | GpgFormat::X509 => {
+                let (program_key, default) = match format {
+                    GpgFormat::X509 => ("gpg.x509.program", "gpgsm"),
+                    _ => ("gpg.program", "gpg"),
+                };
+                let program = config
+                    .signing
+                    .program
+                    .clone()
+                    .or_else(|| git::config_effective(program_key))
+                    .unwrap_or_else(|| default.to_string());
+                match secret_key(&program, key) {
+                    Some(true) => {}
+                    Some(false) => checks.push(Check::fail(
+                        format!("profile '{}': {} signing key {} has no secret key in {}", name, format, key, program),
+                        format!("{} --list-secret-keys, then gamm profile edit {} --signing-key <key>", program, name),
+                    )),
+                    None => checks.push(Check::warn(
+                        format!("profile '{}': can't check {} signing key {}: {} doesn't run", name, format, key, program),
+                        format!("install {} or set the profile's signing program", program),
+                    )),
+                }
+            }
+        }
+    }
+
+    if checks.is_empty() {
+        checks.push(Check::pass("keys named by profiles exist"));
+    }
+    checks
+}
+
+/// Whether `program` (gpg or gpgsm) has a secret key for `key`; None when
+/// it can't be run
+fn has_secret_key(program: &str, key: &str) -> Option<bool> {
+    let status = std::process::Command::new(program)
+        .args(["--batch", "--list-secret-keys", key])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .ok()?;
+    Some(status.success())
+}
+
+/// Run every check
+pub fn run() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let (config_file, repos_file) = match Format::detect() {
+        Format::Json => ("config.json", "repos.json"),
+        Format::Toml => ("gamm.toml (profiles)", "gamm.toml (repositories and rules)"),
+    };
+    // Loading would finish an interrupted save
+    let (config_store, repo_store) = match storage::config_dir().filter(|dir| storage::pending(dir)) {
+        Some(dir) => {
+            checks.push(
+                Check::fail(
+                    "a save of profiles and repositories was interrupted",
+                    "gamm doctor --fix (or any gamm command) finishes it",
+                )
+                .with_fix(Fix::Recover(dir)),
+            );
+            (None, None)
+        }
+        None => (
+            check_store(config_file, ConfigStore::load, &mut checks),
+            check_store(repos_file, RepoStore::load, &mut checks),
+        ),
+    };
+    check_store("settings.json", Settings::load, &mut checks);
+
+    // Hook managers run gamm without the global hooks
+    let global = hooks::global_hooks_path();
+    let integration = git::toplevel().and_then(|toplevel| integration::detect(&toplevel));
+    check_hooks_path(&global, integration, &mut checks);
+    if integration.is_none() || global == HooksPath::Ours {
+        check_hook_scripts(&mut checks);
+    }
+    check_binary(&mut checks);
+
+    if let (Some(config_store), Some(repo_store)) = (&config_store, &repo_store) {
+        checks.extend(check_references(config_store, repo_store));
+    }
+    if let Some(config_store) = &config_store {
+        checks.extend(check_keys(config_store, has_secret_key));
+    }
+
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{Repo, Rule};
+    use crate::store::GitConfig;
+
+    #[test]
+    fn test_check_references() {
+        let mut config_store = ConfigStore::new();
+        config_store.add("work", GitConfig::default());
+        config_store.add("client", GitConfig {
+            extends: Some("gone".into()),
+            ..Default::default()
+        });
+
+        let mut repo_store = RepoStore::new();
+        repo_store.add(Repo {
+            repo_name: "api".into(),
+            url: "git@github.com:acme/api.git".into(),
+            commit_by: "work".into(),
+        });
+        repo_store.add(Repo {
+            repo_name: "old".into(),
+            url: "git@github.com:acme/old.git".into(),
+            commit_by: "former".into(),
+        });
+        repo_store.add_rule(Rule {
+            pattern: "github.com/acme/*".into(),
+            commit_by: "former".into(),
+        });
+
+        let checks = check_references(&config_store, &repo_store);
+        let messages: Vec<&str> = checks.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "profile 'client' extends missing profile 'gone'",
+                "repository 'old' uses missing profile 'former'",
+                "rule github.com/acme/* uses missing profile 'former'",
+            ]
+        );
+        assert!(checks.iter().all(|c| c.level == Level::Fail && c.fix.is_none()));
+
+        repo_store.remove_by_url("git@github.com:acme/old.git");
+        repo_store.remove_rule("github.com/acme/*");
+        config_store.remove("client");
+        let checks = check_references(&config_store, &repo_store);
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].level, Level::Pass);
+    }
+
+    #[test]
+    fn test_check_hooks_path_accepts_hook_managers() {
+        let mut checks = Vec::new();
+        check_hooks_path(&HooksPath::Unset, Some(Integration::Husky), &mut checks);
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].level, Level::Pass);
+        assert!(checks[0].message.contains("through husky") && checks[0].fix.is_none());
+
+        let mut checks = Vec::new();
+        check_hooks_path(&HooksPath::Other("/srv/hooks".into()), Some(Integration::PreCommit), &mut checks);
+        assert_eq!(checks[0].level, Level::Fail);
+        assert!(checks[0].hint.as_ref().unwrap().contains("gamm hook run pre-commit"));
+    }
+
+    #[test]
+    fn test_check_keys_looks_up_gpg_keys() {
+        let signing = |format, key: &str, program: &str| GitConfig {
+            signing: crate::store::SigningConfig {
+                format,
+                key: Some(key.into()),
+                program: Some(program.into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut config_store = ConfigStore::new();
+        config_store.add("a", signing(None, "ABCD1234", "gpg"));
+        config_store.add("b", signing(Some(GpgFormat::X509), "0x1111", "gpgsm"));
+        config_store.add("c", signing(Some(GpgFormat::Openpgp), "GONE", "gpg"));
+        config_store.add("d", signing(None, "ABCD1234", "/opt/missing-gpg"));
+
+        let checks = check_keys(&config_store, |program, key| match program {
+            "gpg" | "gpgsm" => Some(key != "GONE"),
+            _ => None,
+        });
+        let levels: Vec<_> = checks.iter().map(|c| (c.level, c.message.as_str())).collect();
+        assert_eq!(levels.len(), 2, "{:?}", levels);
+        assert_eq!(levels[0].0, Level::Fail);
+        assert!(levels[0].1.contains("profile 'c': openpgp signing key GONE has no secret key in gpg"));
+        assert_eq!(levels[1].0, Level::Warn);
+        assert!(levels[1].1.contains("profile 'd'"));
+    }
+
+    #[test]
+    fn test_check_keys_uses_inherited_fields() {
+        let mut config_store = ConfigStore::new();
+        config_store.add(
+            "base",
+            GitConfig {
+                signing: crate::store::SigningConfig {
+                    format: Some(GpgFormat::Ssh),
+                    ..Default::default()
+                },
+                ssh_key: Some("/nonexistent/id_base".into()),
+                ..Default::default()
+            },
+        );
+        config_store.add(
+            "child",
+            GitConfig {
+                extends: Some("base".into()),
+                signing: crate::store::SigningConfig {
+                    key: Some("/nonexistent/child.pub".into()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        config_store.add(
+            "orphan",
+            GitConfig {
+                extends: Some("gone".into()),
+                ..Default::default()
+            },
+        );
+
+        // The child's key is an SSH file, never looked up with gpg
+        let checks = check_keys(&config_store, |_, _| panic!("gpg must not run for SSH keys"));
+        let messages: Vec<_> = checks.iter().map(|c| (c.level, c.message.as_str())).collect();
+        assert_eq!(messages.len(), 4, "{:?}", messages);
+        assert!(messages.iter().all(|(level, _)| *level == Level::Fail));
+        assert!(messages[0].1.contains("profile 'base': SSH key /nonexistent/id_base not found"));
+        assert!(messages[1].1.contains("profile 'child': SSH key /nonexistent/id_base not found"));
+        assert!(messages[2].1.contains("profile 'child': signing key /nonexistent/child.pub not found"));
+        assert!(messages[3].1.contains("profile 'orphan': keys not checked"));
+    }
+}
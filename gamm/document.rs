@@ -0,0 +1,389 @@
+/*
+ * document.rs
+ * gamm.toml: profiles, repositories and rules in one hand-editable file.
+ *
+ * When ~/.config/gamm/gamm.toml exists it replaces config.json and
+ * repos.json (settings.json stays as it is). Each store owns some top-level
+ * keys of the document (`profiles`; `repos`, `rules` and `dir_rules`) and
+ * only rewrites those. Saving merges the new values into the parsed file
+ * instead of printing it from scratch, so comments, key order and layout
+ * survive wherever the value they belong to is unchanged.
+ *
+ * The document has its own `version` and migrations (`SCHEMA`), separate
+ * from those of the JSON files: an older document is upgraded in memory on
+ * load, and the next save backs it up like the JSON files and writes the
+ * upgraded stores.
+ */
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, ArrayOfTables, DocumentMut, Item, Table, Value};
+
+use crate::schema::Schema;
+use crate::storage;
+
+/// Format the stores are kept in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// config.json and repos.json
+    Json,
+    /// gamm.toml
+    Toml,
+}
+
+impl Format {
+    /// The format in use in the config dir
+    pub fn detect() -> Self {
+        storage::config_dir().map_or(Format::Json, |dir| Self::detect_in(&dir))
+    }
+
+    /// The format in use in `dir`: TOML once gamm.toml exists
+    pub fn detect_in(dir: &Path) -> Self {
+        if dir.join(FILE).exists() { Format::Toml } else { Format::Json }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Json => write!(f, "json"),
+            Format::Toml => write!(f, "toml"),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "toml" => Ok(Format::Toml),
+            _ => Err(format!("unknown format '{}' (expected 'toml' or 'json')", s)),
+        }
+    }
+}
+
+/// File name in the config dir
+pub const FILE: &str = "gamm.toml";
+
+/// Layout history of gamm.toml. Version 1 is a document without `version`,
+/// laid out like version 2.
+pub const SCHEMA: Schema = Schema {
+    file: FILE,
+    current: 2,
+    migrations: &[Ok],
+};
+
+/// Path of the TOML file (~/.config/gamm/gamm.toml)
+pub fn path() -> Option<PathBuf> {
+    storage::config_dir().map(|p| p.join(FILE))
+}
+
+fn invalid(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+}
+
+/// Version of a document (1 without `version`), refusing one written by a
+/// newer gamm
+fn version(path: &Path, doc: &DocumentMut) -> io::Result<u32> {
+    let version = match doc.get("version") {
+        Some(version) => version
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v >= 1)
+            .ok_or_else(|| invalid(path, "version must be a positive integer"))?,
+        None => 1,
+    };
+    SCHEMA.check_version(version).map_err(|e| invalid(path, e))?;
+    Ok(version)
+}
+
+/// Load a store from the document, upgrading an older one in memory. Parse
+/// and type errors point at the line and column.
+pub fn load<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
+    let contents = fs::read_to_string(path)?;
+    let doc: DocumentMut = contents.parse().map_err(|e| invalid(path, e))?;
+    version(path, &doc)?;
+
+    let mut original: serde_json::Value = toml_edit::de::from_str(&contents).map_err(|e| invalid(path, e))?;
+    let (value, _) = SCHEMA.upgrade(original.clone()).map_err(|e| invalid(path, e))?;
+    if let Some(map) = original.as_object_mut() {
+        map.remove("version");
+    }
+
+    // Parse unchanged documents again from the text, so errors have a line
+    // and column
+    if value == original {
+        toml_edit::de::from_str(&contents).map_err(|e| invalid(path, e))
+    } else {
+        serde_json::from_value(value).map_err(|e| invalid(path, e))
+    }
+}
+
+/// Before the document is overwritten, back it up if it has an older version
+pub fn backup(path: &Path) -> io::Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let doc = open(path)?;
+    SCHEMA.backup_from(path, version(path, &doc)?)
+}
+
+/// Read the document for `update`; empty when the file doesn't exist yet
+pub fn open(path: &Path) -> io::Result<DocumentMut> {
+    if !path.exists() {
+        return Ok(DocumentMut::new());
+    }
+    fs::read_to_string(path)?.parse().map_err(|e| invalid(path, e))
+}
+
+/// Merge the `owned` top-level keys of a store into the document, leaving
+/// the rest of it alone
+pub fn update<T: Serialize>(doc: &mut DocumentMut, path: &Path, data: &T, owned: &[&str]) -> io::Result<()> {
+    version(path, doc)?;
+    let mut new = toml_edit::ser::to_document(data).map_err(|e| invalid(path, e))?;
+
+    merge(doc.entry("version").or_insert(Item::None), toml_edit::value(i64::from(SCHEMA.current)));
+    for key in owned {
+        match new.remove(key) {
+            Some(item) => merge(doc.entry(key).or_insert(Item::None), layout(item)),
+            None => {
+                doc.remove(key);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the `owned` top-level keys of a single store into the document
+/// file (`Store::commit_as` writes both stores in one go)
+#[cfg(test)]
+pub fn save<T: Serialize>(path: &Path, data: &T, owned: &[&str]) -> io::Result<()> {
+    let mut doc = open(path)?;
+    update(&mut doc, path, data, owned)?;
+    storage::write_atomic(path, doc.to_string().as_bytes())
+}
+
+/// Lay out a freshly serialized top-level item for reading: maps of
+/// profiles or repositories become one `[section.name]` table per entry,
+/// lists of rules become `[[section]]` tables, and everything below stays
+/// inline.
+fn layout(item: Item) -> Item {
+    match item {
+        Item::Value(Value::InlineTable(inline)) => {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            for (key, entry) in inline.into_table() {
+                let entry = match entry {
+                    Item::Value(Value::InlineTable(entry)) => Item::Table(entry.into_table()),
+                    other => other,
+                };
+                table.insert(&key, entry);
+            }
+            table.sort_values();
+            Item::Table(table)
+        }
+        Item::Value(Value::Array(array)) if !array.is_empty() && array.iter().all(Value::is_inline_table) => {
+            Item::ArrayOfTables(array_of_tables(array))
+        }
+        other => other,
+    }
+}
+
+/// `[[...]]` tables from an array of inline tables
+fn array_of_tables(array: Array) -> ArrayOfTables {
+    let mut tables = ArrayOfTables::new();
+    for value in array {
+        if let Value::InlineTable(entry) = value {
+            tables.push(entry.into_table());
+        }
+    }
+    tables
+}
+
+/// Whether two values are equal, ignoring formatting and comments
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Integer(b)) => a.value() == b.value(),
+        (Value::Float(a), Value::Float(b)) => a.value() == b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Datetime(a), Value::Datetime(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| same(a, b)),
+        (Value::InlineTable(a), Value::InlineTable(b)) => {
+            a.len() == b.len() && a.iter().all(|(key, a)| b.get(key).is_some_and(|b| same(a, b)))
+        }
+        _ => false,
+    }
+}
+
+/// Merge a new item into an existing one, keeping the existing formatting
+/// where the content allows. The new item is first brought into the shape
+/// the file already uses (a standard or an inline table, `[[...]]` or an
+/// inline array).
+fn merge(existing: &mut Item, new: Item) {
+    let new = match (&*existing, new) {
+        (Item::Table(_), Item::Value(Value::InlineTable(table))) => Item::Table(table.into_table()),
+        (Item::Value(Value::InlineTable(_)), Item::Table(table)) => Item::Value(Value::InlineTable(table.into_inline_table())),
+        (Item::ArrayOfTables(_), Item::Value(Value::Array(array))) if array.iter().all(Value::is_inline_table) => {
+            Item::ArrayOfTables(array_of_tables(array))
+        }
+        (_, new) => new,
+    };
+
+    match (existing, new) {
+        (Item::Table(old), Item::Table(new)) => merge_table(old, new),
+        (Item::ArrayOfTables(old), Item::ArrayOfTables(new)) => merge_tables(old, new),
+        (Item::Value(old), Item::Value(mut new)) => {
+            if !same(old, &new) {
+                *new.decor_mut() = old.decor().clone();
+                *old = new;
+            }
+        }
+        (existing, new) => *existing = new,
+    }
+}
+
+fn merge_table(old: &mut Table, new: Table) {
+    let stale: Vec<String> = old
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !new.contains_key(key))
+        .collect();
+    for key in stale {
+        old.remove(&key);
+    }
+
+    for (key, item) in new {
+        match old.get_mut(&key) {
+            Some(existing) => merge(existing, item),
+            None => {
+                old.insert(&key, item);
+            }
+        }
+    }
+}
+
+/// Merge lists of tables, matching entries on their first key (a rule's
+/// pattern) so comments stay with their entry when others are added or
+/// removed
+fn merge_tables(old: &mut ArrayOfTables, new: ArrayOfTables) {
+    let mut previous: Vec<Table> = std::mem::replace(old, ArrayOfTables::new()).into_iter().collect();
+
+    for table in new {
+        let first = table.iter().next().and_then(|(key, item)| Some((key.to_string(), item.as_value()?.clone())));
+        let matching = first.and_then(|(key, value)| {
+            previous
+                .iter()
+                .position(|p| p.get(&key).and_then(Item::as_value).is_some_and(|v| same(v, &value)))
+        });
+        match matching {
+            Some(i) => {
+                let mut entry = previous.remove(i);
+                merge_table(&mut entry, table);
+                old.push(entry);
+            }
+            None => old.push(table),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{RepoStore, Rule};
+    use crate::store::{ConfigStore, GitConfig, UserConfig};
+
+    fn temp_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gamm_document_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("gamm.toml")
+    }
+
+    fn profile(email: &str) -> GitConfig {
+        GitConfig {
+            user: UserConfig {
+                name: "Jane".into(),
+                email: email.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_save_keeps_comments() {
+        let path = temp_file("comments");
+        fs::write(
+            &path,
+            "# my gamm setup\nversion = 2\n\n# day job\n[profiles.work]\nuser = { name = \"Jane\", email = \"jane@acme.com\" }  # keep\n\n[[rules]]\n# acme org\npattern = \"github.com/acme/*\"\ncommit_by = \"work\"\n",
+        )
+        .unwrap();
+
+        let mut config_store: ConfigStore = load(&path).unwrap();
+        assert_eq!(config_store.get("work").unwrap().user.email, "jane@acme.com");
+        config_store.add("home", profile("jane@home.dev"));
+        save(&path, &config_store, &["profiles"]).unwrap();
+
+        let mut repo_store: RepoStore = load(&path).unwrap();
+        repo_store.add_rule(Rule {
+            pattern: "github.com".into(),
+            commit_by: "home".into(),
+        });
+        save(&path, &repo_store, &["repos", "rules", "dir_rules"]).unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with("# my gamm setup\nversion = 2\n\n# day job\n[profiles.work]\n"));
+        assert!(written.contains("}  # keep\n"));
+        assert!(written.contains("[profiles.home]\n"));
+        assert!(written.contains("[[rules]]\n# acme org\npattern = \"github.com/acme/*\"\n"));
+
+        let reloaded: ConfigStore = load(&path).unwrap();
+        assert_eq!(reloaded.get("home").unwrap().user.email, "jane@home.dev");
+        let reloaded: RepoStore = load(&path).unwrap();
+        assert_eq!(reloaded.rules_by_precedence().len(), 2);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_load_errors_have_positions() {
+        let path = temp_file("errors");
+
+        fs::write(&path, "[profiles.work]\nuser = { name = \"Jane\", email = 1 }\n").unwrap();
+        let err = load::<ConfigStore>(&path).unwrap_err().to_string();
+        assert!(err.contains("line 2, column"), "{}", err);
+
+        fs::write(&path, "[profiles.work\n").unwrap();
+        let err = load::<ConfigStore>(&path).unwrap_err().to_string();
+        assert!(err.contains("line 1, column"), "{}", err);
+
+        fs::write(&path, "version = 3\n").unwrap();
+        let err = load::<ConfigStore>(&path).unwrap_err().to_string();
+        assert!(err.contains("newer than this gamm understands"), "{}", err);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_older_document_is_upgraded_and_backed_up() {
+        let path = temp_file("upgrade");
+        let original = "[profiles.work]\nuser = { name = \"Jane\", email = \"jane@acme.com\" }\n";
+        fs::write(&path, original).unwrap();
+
+        let config_store: ConfigStore = load(&path).unwrap();
+        assert_eq!(config_store.get("work").unwrap().user.email, "jane@acme.com");
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+        let backup = backup(&path).unwrap().unwrap();
+        assert_eq!(backup, Schema::backup_path(&path, 1));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), original);
+        save(&path, &config_store, &["profiles"]).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().starts_with("version = 2\n"));
+        assert!(super::backup(&path).unwrap().is_none());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
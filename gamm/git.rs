@@ -0,0 +1,257 @@
+/*
+ * git.rs
+ * Thin wrappers around the `git` CLI used to read and write configuration.
+ *
+ * Every helper takes the Scope it operates on so callers never have to
+ * spell out `--global` / `--local` themselves.
+ */
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::repo::{self, Remote};
+use crate::store::Scope;
+
+/// Local config key naming the remote that decides a repository's profile
+pub const REMOTE_PIN_KEY: &str = "gamm.remote";
+
+/// Real git binary, set when gamm runs as the `git` shim so that our own
+/// git invocations don't loop back into the shim
+static GIT_BINARY: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use an explicit git binary for every command built by `git()`
+pub fn set_binary(path: PathBuf) {
+    let _ = GIT_BINARY.set(path);
+}
+
+/// Build a `git` command
+pub fn git() -> Command {
+    match GIT_BINARY.get() {
+        Some(path) => Command::new(path),
+        None => Command::new("git"),
+    }
+}
+
+fn scope_flag(scope: Scope) -> &'static str {
+    match scope {
+        Scope::Global => "--global",
+        Scope::Local => "--local",
+    }
+}
+
+/// Run a git command and turn a non-zero exit status into an error
+fn run(cmd: &mut Command) -> io::Result<()> {
+    let status = cmd.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("git exited with {}", status)))
+    }
+}
+
+/// Run `git config <args>` and return its trimmed output, if any
+fn read_value(args: &[&str]) -> Option<String> {
+    let output = git().arg("config").args(args).output().ok()?;
+
+    if output.status.success() {
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !value.is_empty() {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Read a single value from the given scope
+pub fn config_get(scope: Scope, key: &str) -> Option<String> {
+    read_value(&[scope_flag(scope), "--get", key])
+}
+
+/// Read the effective value of a key, as git itself would resolve it
+/// (all scopes, including `include`/`includeIf` files)
+pub fn config_effective(key: &str) -> Option<String> {
+    read_value(&["--get", key])
+}
+
+/// Read every value of a (possibly multi-valued) key from the given scope
+pub fn config_get_all(scope: Scope, key: &str) -> Vec<String> {
+    let output = match git()
+        .args(["config", scope_flag(scope), "--get-all", key])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|l| l.to_string())
+        .collect()
+}
+
+/// Read every value of a key from all scopes as (scope, value), in the
+/// order git reads them; the last one wins for single-valued keys
+pub fn config_origins(key: &str) -> Vec<(String, String)> {
+    read_origins(&[key]).unwrap_or_default()
+}
+
+/// Like `config_origins`, with boolean values spelled `true` or `false`
+/// whichever way they were written (`yes`, `on`, `1`, ...). When a value
+/// isn't a boolean, every value is returned as written.
+pub fn config_origins_bool(key: &str) -> Vec<(String, String)> {
+    read_origins(&["--type=bool", key]).unwrap_or_else(|| config_origins(key))
+}
+
+fn read_origins(args: &[&str]) -> Option<Vec<(String, String)>> {
+    let output = match git()
+        .args(["config", "--show-scope", "--get-all"])
+        .args(args)
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return None,
+    };
+
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (scope, value) = line.split_once('\t')?;
+                Some((scope.to_string(), value.to_string()))
+            })
+            .collect(),
+    )
+}
+
+/// Set a single-valued key in the given scope, replacing every value it had
+pub fn config_set(scope: Scope, key: &str, value: &str) -> io::Result<()> {
+    run(git().args(["config", scope_flag(scope), "--replace-all", key, value]))
+}
+
+/// Add a value to a (possibly multi-valued) key in the given scope
+pub fn config_add(scope: Scope, key: &str, value: &str) -> io::Result<()> {
+    run(git().args(["config", scope_flag(scope), "--add", key, value]))
+}
+
+/// Remove every value of a key from the given scope (no-op if unset)
+pub fn config_unset_all(scope: Scope, key: &str) -> io::Result<()> {
+    let status = git()
+        .args(["config", scope_flag(scope), "--unset-all", key])
+        .status()?;
+
+    // Exit code 5 means the key was not set, which is fine here
+    match status.code() {
+        Some(0) | Some(5) => Ok(()),
+        _ => Err(io::Error::other(format!("git exited with {}", status))),
+    }
+}
+
+/// Remove one value of a (possibly multi-valued) key from the given scope,
+/// keeping its other values (no-op if absent)
+pub fn config_unset_value(scope: Scope, key: &str, value: &str) -> io::Result<()> {
+    let status = git()
+        .args(["config", scope_flag(scope), "--fixed-value", "--unset-all", key, value])
+        .status()?;
+
+    // Exit code 5 means the value was not set, which is fine here
+    match status.code() {
+        Some(0) | Some(5) => Ok(()),
+        _ => Err(io::Error::other(format!("git exited with {}", status))),
+    }
+}
+
+/// Get the URL of a remote of the repository in the current directory
+pub fn remote_url(name: &str) -> Option<String> {
+    let output = git().args(["remote", "get-url", name]).output().ok()?;
+
+    if output.status.success() {
+        let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !url.is_empty() {
+            return Some(url);
+        }
+    }
+
+    None
+}
+
+/// All remotes of the repository in the current directory as (name, url)
+pub fn remotes() -> Vec<(String, String)> {
+    let output = match git()
+        .args(["config", "--get-regexp", r"^remote\..*\.url$"])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (key, url) = line.split_once(' ')?;
+            let name = key.strip_prefix("remote.")?.strip_suffix(".url")?;
+            Some((name.to_string(), url.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Remotes to resolve the current repository from, in priority order.
+/// If the repository pins a remote that still exists, only that one is used.
+pub fn ordered_remotes(priority: &[String]) -> Vec<Remote> {
+    let remotes = repo::order_remotes(remotes(), priority);
+    match config_get(Scope::Local, REMOTE_PIN_KEY) {
+        Some(pinned) if remotes.iter().any(|r| r.name == pinned) => {
+            remotes.into_iter().filter(|r| r.name == pinned).collect()
+        }
+        _ => remotes,
+    }
+}
+
+/// Add a `Key: value` trailer to a commit message file. `if_exists` is
+/// passed to `git interpret-trailers --if-exists` (e.g. `addIfDifferent`).
+pub fn add_trailer(file: &Path, trailer: &str, if_exists: &str) -> io::Result<()> {
+    run(git()
+        .args(["interpret-trailers", "--in-place", "--if-missing", "add"])
+        .args(["--if-exists", if_exists, "--trailer", trailer])
+        .arg(file))
+}
+
+/// Hash data the way git hashes a blob
+pub fn hash_object(data: &str) -> Option<String> {
+    let mut child = git()
+        .args(["hash-object", "--stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(data.as_bytes()).ok()?;
+
+    let output = child.wait_with_output().ok()?;
+    let hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !hash.is_empty()).then_some(hash)
+}
+
+/// Top-level directory of the work tree containing the current directory
+pub fn toplevel() -> Option<PathBuf> {
+    let output = git().args(["rev-parse", "--show-toplevel"]).output().ok()?;
+
+    if output.status.success() {
+        let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !path.is_empty() {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    None
+}
+
+/// Check whether the current directory is inside a git work tree
+pub fn in_work_tree() -> bool {
+    git()
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
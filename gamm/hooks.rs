@@ -0,0 +1,314 @@
+/*
+ * hooks.rs
+ * Git hook scripts gamm installs into ~/.githooks.
+ *
+ * Every hook gets a gamm-managed section (see section.rs), so anything else
+ * the user keeps in the same script is left alone.
+ *
+ * - pre-commit: resolve the repository and switch identity
+ * - prepare-commit-msg / commit-msg: add the profile's sign-off and trailers
+ * - post-commit: put back a global identity saved before switching
+ *
+ * Pointing the global core.hooksPath at ~/.githooks stops git from running
+ * each repository's own hooks, so every gamm section ends by handing over to
+ * the repository's hook of the same name (in .git/hooks, or in the
+ * repository's own core.hooksPath). Every other hook git runs from the hooks
+ * directory is installed with only that hand-over, so frameworks like
+ * pre-commit keep working. push-to-checkout replaces git's own work tree
+ * update when it exists, so without a repository hook it does that update.
+ */
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::git;
+use crate::section;
+use crate::storage;
+use crate::store::{self, Scope};
+
+pub const PRE_COMMIT: &str = "pre-commit";
+pub const POST_COMMIT: &str = "post-commit";
+pub const PREPARE_COMMIT_MSG: &str = "prepare-commit-msg";
+pub const COMMIT_MSG: &str = "commit-msg";
+
+/// Body of the gamm section in the pre-commit hook (markers are added by `section::wrap`)
+const PRE_COMMIT_BODY: &str = r#"
+REPO_PATH=$(git rev-parse --show-toplevel 2>/dev/null || pwd)
+
+echo "gamm: checking ..."
+gamm pre-commit --path "$REPO_PATH""#;
+
+/// Body of the gamm section in the post-commit hook. The commit already
+/// exists at this point, so a failure must not look like a failed commit.
+const POST_COMMIT_BODY: &str = r#"
+gamm post-commit || true"#;
+
+/// Body of the gamm section in the prepare-commit-msg hook
+const PREPARE_COMMIT_MSG_BODY: &str = r#"
+gamm prepare-commit-msg "$1""#;
+
+/// Body of the gamm section in the commit-msg hook
+const COMMIT_MSG_BODY: &str = r#"
+gamm commit-msg "$1""#;
+
+/// Appended to every gamm section: run the repository's own hook of the same
+/// name with the same arguments and stdin, failing with its exit code.
+/// A repository hooksPath that is ~/.githooks itself is skipped. Without a
+/// push-to-checkout hook, update the work tree the way git does when there
+/// is none (receive.denyCurrentBranch=updateInstead).
+const CHAIN_BODY: &str = r#"
+# Hand over to the repository's own hook
+gamm_hooks=$(git config --local core.hooksPath || true)
+case "$gamm_hooks" in
+    "") gamm_hooks="$(git rev-parse --git-common-dir)/hooks" ;;
+    "~/"*) gamm_hooks="$HOME/${gamm_hooks#"~/"}" ;;
+    /*) ;;
+    *) gamm_hooks="$(git rev-parse --show-toplevel)/$gamm_hooks" ;;
+esac
+gamm_hook="$gamm_hooks/$(basename "$0")"
+if [ -x "$gamm_hook" ] && [ "$(cd "$gamm_hooks" && pwd -P)" != "$(cd "$(dirname "$0")" && pwd -P)" ]; then
+    "$gamm_hook" "$@" || exit $?
+elif [ "$(basename "$0")" = push-to-checkout ]; then
+    git update-index -q --ignore-submodules --refresh
+    gamm_head=$(git rev-parse -q --verify HEAD || git hash-object -t tree /dev/null)
+    git diff-files --quiet --ignore-submodules -- || { echo "Working directory has unstaged changes" >&2; exit 1; }
+    git diff-index --quiet --cached --ignore-submodules "$gamm_head" -- || { echo "Working directory has staged changes" >&2; exit 1; }
+    git read-tree -u -m "$gamm_head" "$1"
+fi"#;
+
+/// Hooks managed by gamm, with the body of their gamm section: every hook
+/// git runs from the hooks directory (see githooks(5)). Hooks with an empty
+/// body only hand over to the repository's hook. proc-receive is left out,
+/// since git only runs it when configured and it must speak a protocol.
+pub const HOOKS: &[(&str, &str)] = &[
+    ("applypatch-msg", ""),
+    ("pre-applypatch", ""),
+    ("post-applypatch", ""),
+    (PRE_COMMIT, PRE_COMMIT_BODY),
+    ("pre-merge-commit", ""),
+    (PREPARE_COMMIT_MSG, PREPARE_COMMIT_MSG_BODY),
+    (COMMIT_MSG, COMMIT_MSG_BODY),
+    (POST_COMMIT, POST_COMMIT_BODY),
+    ("pre-rebase", ""),
+    ("post-checkout", ""),
+    ("post-merge", ""),
+    ("pre-push", ""),
+    ("pre-receive", ""),
+    ("update", ""),
+    ("post-receive", ""),
+    ("post-update", ""),
+    ("reference-transaction", ""),
+    ("push-to-checkout", ""),
+    ("pre-auto-gc", ""),
+    ("post-rewrite", ""),
+    ("sendemail-validate", ""),
+    ("post-index-change", ""),
+    ("p4-changelist", ""),
+    ("p4-prepare-changelist", ""),
+    ("p4-post-changelist", ""),
+    ("p4-pre-submit", ""),
+];
+
+/// What `install` did to a hook script
+#[derive(Debug, PartialEq)]
+pub enum Installed {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+/// What `remove` did to a hook script
+#[derive(Debug, PartialEq)]
+pub enum Removed {
+    /// There is no such hook script
+    Missing,
+    /// The script has no gamm section
+    NotManaged,
+    /// The gamm section was removed, other content kept
+    Stripped,
+    /// The script only contained gamm and was deleted
+    Deleted,
+}
+
+pub fn githooks_dir() -> PathBuf {
+    dirs::home_dir()
+        .expect("Could not find home directory")
+        .join(".githooks")
+}
+
+/// What the global core.hooksPath is set to
+#[derive(Debug, PartialEq)]
+pub enum HooksPath {
+    Unset,
+    /// Points at the gamm hooks directory
+    Ours,
+    /// Points somewhere else (value as configured)
+    Other(String),
+}
+
+/// Read the global core.hooksPath
+pub fn global_hooks_path() -> HooksPath {
+    match git::config_get(Scope::Global, "core.hooksPath") {
+        None => HooksPath::Unset,
+        Some(path) if store::expand_home(&path, dirs::home_dir().as_deref()) == githooks_dir() => HooksPath::Ours,
+        Some(path) => HooksPath::Other(path),
+    }
+}
+
+/// Point the global core.hooksPath at the gamm hooks directory
+pub fn enable() -> io::Result<()> {
+    git::config_set(Scope::Global, "core.hooksPath", &githooks_dir().display().to_string())
+}
+
+/// Path of a hook script in the hooks directory
+pub fn hook_path(name: &str) -> PathBuf {
+    githooks_dir().join(name)
+}
+
+/// New content of a hook script with the gamm section added or refreshed.
+/// A config dir given with --config-dir is exported for the gamm commands
+/// the hook runs, since they only see the environment.
+fn render(existing: Option<&str>, body: &str, config_dir: Option<&Path>) -> String {
+    let export = config_dir.map_or(String::new(), |dir| {
+        format!("\nexport GAMM_CONFIG_DIR={}", store::shell_quote(&dir.display().to_string()))
+    });
+    let body = format!("{}{}\n{}", export, body, CHAIN_BODY);
+    match existing {
+        Some(existing) => section::upsert(existing, &body),
+        None => format!("#!/bin/sh\nset -e\n\n{}\n", section::wrap(&body)),
+    }
+}
+
+/// Content of a hook script without the gamm section, or None if nothing
+/// but the shebang and `set -e` would be left
+fn without_section(content: &str) -> Option<String> {
+    let rest = section::strip(content);
+    let is_empty = rest
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with("#!") && !l.starts_with("set -e"))
+        .count()
+        == 0;
+
+    if is_empty { None } else { Some(format!("{}\n", rest)) }
+}
+
+/// State of an installed hook script
+#[derive(Debug, PartialEq)]
+pub enum HookState {
+    Missing,
+    /// The script exists but has no gamm section
+    NotManaged,
+    /// The gamm section differs from what this version installs
+    Outdated,
+    NotExecutable,
+    Current,
+}
+
+/// Check a hook script against what `install` would write
+pub fn state(name: &str, body: &str) -> io::Result<HookState> {
+    let path = hook_path(name);
+    if !path.exists() {
+        return Ok(HookState::Missing);
+    }
+
+    let content = fs::read_to_string(&path)?;
+    Ok(if !section::contains(&content) {
+        HookState::NotManaged
+    } else if render(Some(&content), body, storage::config_dir_flag().as_deref()) != content {
+        HookState::Outdated
+    } else if fs::metadata(&path)?.permissions().mode() & 0o111 == 0 {
+        HookState::NotExecutable
+    } else {
+        HookState::Current
+    })
+}
+
+/// Make a hook script executable (chmod +x)
+pub fn make_executable(name: &str) -> io::Result<()> {
+    let path = hook_path(name);
+    let mut perms = fs::metadata(&path)?.permissions();
+    perms.set_mode(0o755);
+    fs::set_permissions(&path, perms)
+}
+
+/// Add or refresh the gamm section of a hook script
+pub fn install(name: &str, body: &str) -> io::Result<Installed> {
+    let path = hook_path(name);
+    fs::create_dir_all(githooks_dir())?;
+
+    let existing = if path.exists() {
+        Some(fs::read_to_string(&path)?)
+    } else {
+        None
+    };
+
+    let content = render(existing.as_deref(), body, storage::config_dir_flag().as_deref());
+    if existing.as_deref() == Some(content.as_str()) {
+        return Ok(Installed::Unchanged);
+    }
+
+    fs::write(&path, content)?;
+    make_executable(name)?;
+
+    Ok(if existing.is_some() { Installed::Updated } else { Installed::Created })
+}
+
+/// Remove the gamm section of a hook script, deleting the script if only
+/// gamm was in it
+pub fn remove(name: &str) -> io::Result<Removed> {
+    let path = hook_path(name);
+    if !path.exists() {
+        return Ok(Removed::Missing);
+    }
+
+    let content = fs::read_to_string(&path)?;
+    if !section::contains(&content) {
+        return Ok(Removed::NotManaged);
+    }
+
+    match without_section(&content) {
+        Some(rest) => {
+            fs::write(&path, rest)?;
+            Ok(Removed::Stripped)
+        }
+        None => {
+            fs::remove_file(&path)?;
+            Ok(Removed::Deleted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_new_and_existing_hooks() {
+        let created = render(None, POST_COMMIT_BODY, None);
+        assert!(created.starts_with("#!/bin/sh\nset -e\n\n# >>> gamm\n"));
+        assert!(created.contains("gamm post-commit || true\n\n# Hand over"));
+        assert!(created.contains("    \"$gamm_hook\" \"$@\" || exit $?\nelif"));
+        assert!(created.ends_with("git read-tree -u -m \"$gamm_head\" \"$1\"\nfi\n# <<< gamm\n"));
+
+        let existing = "#!/bin/sh\nmake lint\n";
+        let updated = render(Some(existing), POST_COMMIT_BODY, None);
+        assert!(updated.starts_with("#!/bin/sh\nmake lint\n\n# >>> gamm\n"));
+        assert_eq!(render(Some(&updated), POST_COMMIT_BODY, None), updated);
+
+        // Hooks installed with --config-dir keep using that directory
+        let dir = Path::new("/srv/gamm work");
+        let exported = render(Some(&updated), POST_COMMIT_BODY, Some(dir));
+        assert!(exported.contains("# >>> gamm\n\nexport GAMM_CONFIG_DIR='/srv/gamm work'\ngamm post-commit"));
+        assert_ne!(exported, updated);
+    }
+
+    #[test]
+    fn test_without_section() {
+        assert!(without_section(&render(None, PRE_COMMIT_BODY, None)).is_none());
+
+        let mixed = render(Some("#!/bin/sh\nmake lint\n"), PRE_COMMIT_BODY, None);
+        assert_eq!(without_section(&mixed).as_deref(), Some("#!/bin/sh\nmake lint\n"));
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * identity.rs
+ * Snapshot of the global identity taken before gamm switches it.
+ *
+ * With `restore-identity` enabled, applying a profile in global scope first
+ * records the current global values of every key it is about to write
+ * (~/.config/gamm/identity.json). The post-commit hook puts them back and
+ * removes the snapshot.
+ *
+ * A snapshot that is still pending when another switch happens is kept as
+ * is, so the values restored are always the ones from before gamm started
+ * switching, even if a commit failed or was aborted in between. Only the
+ * post-commit hook (or `gamm restore`) restores it, so the pre-commit hook
+ * never aborts a commit over it.
+ */
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::git;
+use crate::storage;
+use crate::store::Scope;
+
+/// Global config values saved before a switch
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Profile applied on top of the saved values
+    pub profile: String,
+    /// Key -> every global value it had (empty if it was unset)
+    pub values: BTreeMap<String, Vec<String>>,
+}
+
+impl Snapshot {
+    /// File name in the config dir
+    const FILE: &'static str = "identity.json";
+
+    /// Get the snapshot file path (~/.config/gamm/identity.json)
+    pub fn snapshot_path() -> Option<PathBuf> {
+        storage::config_dir().map(|p| p.join(Self::FILE))
+    }
+
+    /// Load the pending snapshot, if there is one
+    pub fn load() -> io::Result<Option<Self>> {
+        storage::read_json(Self::FILE)
+    }
+
+    /// Save the snapshot
+    pub fn save(&self) -> io::Result<()> {
+        storage::write_json(Self::FILE, self)
+    }
+
+    /// Delete the snapshot file
+    pub fn clear() -> io::Result<()> {
+        match Self::snapshot_path() {
+            Some(path) if path.exists() => fs::remove_file(path),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record the current values of keys that are not saved yet, leaving
+    /// already saved keys untouched
+    pub fn record<'a>(
+        &mut self,
+        keys: impl IntoIterator<Item = &'a str>,
+        read: impl Fn(&str) -> Vec<String>,
+    ) {
+        for key in keys {
+            if !self.values.contains_key(key) {
+                self.values.insert(key.to_string(), read(key));
+            }
+        }
+    }
+
+    /// Write the saved values back to the global config
+    pub fn restore(&self) -> io::Result<()> {
+        for (key, values) in &self.values {
+            git::config_unset_all(Scope::Global, key)?;
+            for value in values {
+                git::config_add(Scope::Global, key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Save the global values of `keys` before `profile` overwrites them,
+/// extending a pending snapshot instead of replacing it
+pub fn save_before_switch(profile: &str, keys: &[String]) -> io::Result<()> {
+    let _lock = storage::lock()?;
+    let mut snapshot = Snapshot::load()?.unwrap_or_default();
+    snapshot.profile = profile.to_string();
+    snapshot.record(keys.iter().map(String::as_str), |key| {
+        git::config_get_all(Scope::Global, key)
+    });
+    snapshot.save()
+}
+
+/// Follow a profile rename in the pending snapshot, if there is one
+pub fn rename_profile(old: &str, new: &str) -> io::Result<()> {
+    let _lock = storage::lock()?;
+    match Snapshot::load()? {
+        Some(mut snapshot) if snapshot.profile == old => {
+            snapshot.profile = new.to_string();
+            snapshot.save()
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Restore and delete the pending snapshot. Returns the restored snapshot.
+pub fn restore_pending() -> io::Result<Option<Snapshot>> {
+    let _lock = storage::lock()?;
+    let Some(snapshot) = Snapshot::load()? else {
+        return Ok(None);
+    };
+
+    snapshot.restore()?;
+    Snapshot::clear()?;
+    Ok(Some(snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_keeps_values_from_before_the_first_switch() {
+        let mut snapshot = Snapshot::default();
+        snapshot.record(["user.name", "user.email"], |key| match key {
+            "user.name" => vec!["Me".to_string()],
+            _ => vec![],
+        });
+
+        // A second switch sees gamm's values; they must not replace the originals
+        snapshot.record(["user.name", "commit.gpgsign"], |_| vec!["Work".to_string()]);
+
+        assert_eq!(snapshot.values["user.name"], vec!["Me"]);
+        assert!(snapshot.values["user.email"].is_empty());
+        assert_eq!(snapshot.values["commit.gpgsign"], vec!["Work"]);
+    }
+}
@@ -0,0 +1,316 @@
+/*
+ * integration.rs
+ * Run gamm as one hook among many from a hook manager instead of through a
+ * global core.hooksPath.
+ *
+ * - pre-commit: a `repo: local` entry in .pre-commit-config.yaml
+ * - lefthook: commands in lefthook.yml
+ * - husky: lines in .husky/<hook>
+ *
+ * Every snippet calls `gamm hook run <hook>`, which finds the repository
+ * and its remotes itself. What gamm inserts is wrapped in the usual managed
+ * section (see section.rs), so running `init --integration` again refreshes
+ * it in place.
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::hooks::Installed;
+use crate::section;
+
+/// A hook manager gamm can be added to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integration {
+    PreCommit,
+    Lefthook,
+    Husky,
+}
+
+impl Integration {
+    pub const ALL: [Integration; 3] = [Integration::PreCommit, Integration::Lefthook, Integration::Husky];
+}
+
+impl std::fmt::Display for Integration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Integration::PreCommit => write!(f, "pre-commit"),
+            Integration::Lefthook => write!(f, "lefthook"),
+            Integration::Husky => write!(f, "husky"),
+        }
+    }
+}
+
+impl std::str::FromStr for Integration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Integration::ALL
+            .into_iter()
+            .find(|i| i.to_string() == s)
+            .ok_or_else(|| format!("unknown integration '{}' (expected 'pre-commit', 'lefthook' or 'husky')", s))
+    }
+}
+
+/// Config files lefthook reads, in the order it looks for them
+const LEFTHOOK_FILES: &[&str] = &["lefthook.yml", "lefthook.yaml", ".lefthook.yml", ".lefthook.yaml"];
+
+/// Hooks lefthook.yml gets commands for
+const LEFTHOOK_HOOKS: &[&str] = &["pre-commit", "commit-msg", "post-commit"];
+
+/// Husky hook files and the line gamm adds to each
+const HUSKY_HOOKS: &[(&str, &str)] = &[
+    ("pre-commit", "gamm hook run pre-commit"),
+    ("commit-msg", "gamm hook run commit-msg \"$1\""),
+    ("post-commit", "gamm hook run post-commit || true"),
+];
+
+/// `repo: local` entry for .pre-commit-config.yaml, as a list item at `indent`
+fn pre_commit_entry(indent: &str) -> String {
+    let lines = [
+        "- repo: local",
+        "  hooks:",
+        "    - id: gamm",
+        "      name: gamm identity",
+        "      entry: gamm hook run pre-commit",
+        "      language: system",
+        "      pass_filenames: false",
+        "      always_run: true",
+        "      stages: [pre-commit]",
+        "    - id: gamm-trailers",
+        "      name: gamm sign-off and trailers",
+        "      entry: gamm hook run commit-msg",
+        "      language: system",
+        "      stages: [commit-msg]",
+        "    - id: gamm-restore",
+        "      name: gamm global identity restore",
+        "      entry: gamm hook run post-commit",
+        "      language: system",
+        "      pass_filenames: false",
+        "      always_run: true",
+        "      stages: [post-commit]",
+    ];
+    lines.iter().map(|l| format!("{}{}", indent, l)).collect::<Vec<_>>().join("\n")
+}
+
+/// Commands for lefthook.yml. The pre-commit command may prompt for a
+/// profile, so it needs the terminal.
+fn lefthook_commands() -> String {
+    [
+        "pre-commit:",
+        "  commands:",
+        "    gamm:",
+        "      run: gamm hook run pre-commit",
+        "      interactive: true",
+        "commit-msg:",
+        "  commands:",
+        "    gamm:",
+        "      run: gamm hook run commit-msg {1}",
+        "post-commit:",
+        "  commands:",
+        "    gamm:",
+        "      run: gamm hook run post-commit",
+    ]
+    .join("\n")
+}
+
+/// The snippet to add by hand, with the file it belongs in
+pub fn snippet(integration: Integration) -> String {
+    match integration {
+        Integration::PreCommit => format!(
+            "# .pre-commit-config.yaml\nrepos:\n{}\n",
+            pre_commit_entry("  ")
+        ),
+        Integration::Lefthook => format!("# lefthook.yml\n{}\n", lefthook_commands()),
+        Integration::Husky => HUSKY_HOOKS
+            .iter()
+            .map(|(hook, line)| format!("# .husky/{}\n{}\n", hook, line))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Add the gamm entry to the `repos:` list of a pre-commit config
+fn insert_pre_commit(content: &str) -> Result<String, String> {
+    if let Some(updated) = section::replace(content, &pre_commit_entry(&list_indent(content))) {
+        return Ok(updated);
+    }
+    if content.trim().is_empty() {
+        return Ok(format!("repos:\n{}\n", section::wrap(&pre_commit_entry("  "))));
+    }
+
+    let lines: Vec<&str> = content.lines().collect();
+    let repos = lines
+        .iter()
+        .position(|l| l.trim_end() == "repos:")
+        .ok_or("no `repos:` list found")?;
+
+    let mut out: Vec<String> = lines[..=repos].iter().map(|l| l.to_string()).collect();
+    out.push(section::wrap(&pre_commit_entry(&list_indent(content))));
+    out.extend(lines[repos + 1..].iter().map(|l| l.to_string()));
+    Ok(format!("{}\n", out.join("\n")))
+}
+
+/// Indentation of the items of the `repos:` list (pre-commit's own sample
+/// uses none, most configs use two spaces)
+fn list_indent(content: &str) -> String {
+    content
+        .lines()
+        .skip_while(|l| l.trim_end() != "repos:")
+        .skip(1)
+        .find(|l| l.trim_start().starts_with("- "))
+        .map(|l| l[..l.len() - l.trim_start().len()].to_string())
+        .unwrap_or_else(|| "  ".to_string())
+}
+
+/// Add the gamm commands to a lefthook config, unless it already configures
+/// the same hooks (YAML can't repeat a key, so those need merging by hand)
+fn insert_lefthook(content: &str) -> Result<String, String> {
+    if let Some(updated) = section::replace(content, &lefthook_commands()) {
+        return Ok(updated);
+    }
+
+    let taken: Vec<&str> = LEFTHOOK_HOOKS
+        .iter()
+        .copied()
+        .filter(|hook| content.lines().any(|l| l.trim_end() == format!("{}:", hook)))
+        .collect();
+    if !taken.is_empty() {
+        return Err(format!("it already configures {}", taken.join(", ")));
+    }
+
+    Ok(section::upsert(content, &lefthook_commands()))
+}
+
+/// Write a file if its content changes
+fn write(path: &Path, existing: Option<&str>, content: &str) -> io::Result<Installed> {
+    if existing == Some(content) {
+        return Ok(Installed::Unchanged);
+    }
+    fs::write(path, content)?;
+    Ok(if existing.is_some() { Installed::Updated } else { Installed::Created })
+}
+
+fn read(path: &Path) -> io::Result<Option<String>> {
+    if path.exists() { fs::read_to_string(path).map(Some) } else { Ok(None) }
+}
+
+/// Add gamm to the hook manager's config in the repository at `toplevel`.
+/// Returns every file written.
+pub fn install(integration: Integration, toplevel: &Path) -> io::Result<Vec<(PathBuf, Installed)>> {
+    let invalid = |path: &Path, reason: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("can't add gamm to {}: {}", path.display(), reason),
+        )
+    };
+
+    match integration {
+        Integration::PreCommit => {
+            let path = toplevel.join(".pre-commit-config.yaml");
+            let existing = read(&path)?;
+            let content = insert_pre_commit(existing.as_deref().unwrap_or_default()).map_err(|e| invalid(&path, e))?;
+            Ok(vec![(path.clone(), write(&path, existing.as_deref(), &content)?)])
+        }
+        Integration::Lefthook => {
+            let path = LEFTHOOK_FILES
+                .iter()
+                .map(|name| toplevel.join(name))
+                .find(|p| p.exists())
+                .unwrap_or_else(|| toplevel.join(LEFTHOOK_FILES[0]));
+            let existing = read(&path)?;
+            let content = insert_lefthook(existing.as_deref().unwrap_or_default()).map_err(|e| invalid(&path, e))?;
+            Ok(vec![(path.clone(), write(&path, existing.as_deref(), &content)?)])
+        }
+        Integration::Husky => {
+            let dir = toplevel.join(".husky");
+            if !dir.is_dir() {
+                return Err(invalid(&dir, "husky is not set up here (run `npx husky init` first)".to_string()));
+            }
+
+            let mut written = Vec::new();
+            for (hook, line) in HUSKY_HOOKS {
+                let path = dir.join(hook);
+                let existing = read(&path)?;
+                let content = section::upsert(existing.as_deref().unwrap_or_default(), line);
+                written.push((path.clone(), write(&path, existing.as_deref(), &content)?));
+            }
+            Ok(written)
+        }
+    }
+}
+
+/// The hook manager that runs gamm in the repository at `toplevel`: the
+/// first whose config has gamm's section or calls `gamm hook run pre-commit`
+pub fn detect(toplevel: &Path) -> Option<Integration> {
+    let runs_gamm = |path: PathBuf| {
+        read(&path)
+            .ok()
+            .flatten()
+            .is_some_and(|content| section::contains(&content) || content.contains("gamm hook run pre-commit"))
+    };
+
+    if runs_gamm(toplevel.join(".pre-commit-config.yaml")) {
+        Some(Integration::PreCommit)
+    } else if LEFTHOOK_FILES.iter().any(|name| runs_gamm(toplevel.join(name))) {
+        Some(Integration::Lefthook)
+    } else if runs_gamm(toplevel.join(".husky").join("pre-commit")) {
+        Some(Integration::Husky)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_pre_commit() {
+        let config = "repos:\n-   repo: https://github.com/pre-commit/pre-commit-hooks\n    rev: v4.6.0\n";
+        let updated = insert_pre_commit(config).unwrap();
+        assert!(updated.starts_with("repos:\n# >>> gamm\n- repo: local\n  hooks:\n"));
+        assert!(updated.ends_with("# <<< gamm\n-   repo: https://github.com/pre-commit/pre-commit-hooks\n    rev: v4.6.0\n"));
+        assert_eq!(insert_pre_commit(&updated).unwrap(), updated);
+
+        let indented = insert_pre_commit("repos:\n  - repo: local\n    hooks: []\n").unwrap();
+        assert!(indented.contains("\n  - repo: local\n    hooks:\n      - id: gamm\n"));
+
+        assert!(insert_pre_commit("").unwrap().starts_with("repos:\n# >>> gamm\n  - repo: local\n"));
+        assert!(insert_pre_commit("default_stages: [pre-commit]\n").is_err());
+    }
+
+    #[test]
+    fn test_insert_lefthook() {
+        let created = insert_lefthook("").unwrap();
+        assert!(created.contains("      run: gamm hook run pre-commit\n"));
+        assert_eq!(insert_lefthook(&created).unwrap(), created);
+
+        let other = insert_lefthook("pre-push:\n  commands:\n    test:\n      run: make test\n").unwrap();
+        assert!(other.starts_with("pre-push:\n"));
+
+        let taken = insert_lefthook("pre-commit:\n  commands:\n    lint:\n      run: make lint\n");
+        assert_eq!(taken.unwrap_err(), "it already configures pre-commit");
+    }
+
+    #[test]
+    fn test_detect() {
+        let dir = std::env::temp_dir().join(format!("gamm_integration_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join(".husky")).unwrap();
+        assert_eq!(detect(&dir), None);
+
+        // Other hooks don't count
+        fs::write(dir.join(".husky/pre-commit"), "npm test\n").unwrap();
+        assert_eq!(detect(&dir), None);
+        fs::write(dir.join(".husky/pre-commit"), "npm test\ngamm hook run pre-commit\n").unwrap();
+        assert_eq!(detect(&dir), Some(Integration::Husky));
+
+        install(Integration::Lefthook, &dir).unwrap();
+        assert_eq!(detect(&dir), Some(Integration::Lefthook));
+        install(Integration::PreCommit, &dir).unwrap();
+        assert_eq!(detect(&dir), Some(Integration::PreCommit));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,26 @@
 mod command;
+mod database;
+mod doctor;
+mod document;
+mod git;
+mod hooks;
+mod identity;
+mod integration;
+mod managed;
 mod repo;
+mod schema;
+mod section;
+mod settings;
+mod shim;
+mod status;
+mod storage;
 mod store;
+mod sync;
+mod trailers;
+mod url;
 
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -10,6 +28,11 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 #[command(name = "gamm")]
 #[command(about = "Git Account Manager - Manage multiple git configurations", long_about = None)]
 struct Cli {
+    /// Keep profiles, repositories and settings in this directory
+    /// [default: $GAMM_CONFIG_DIR, $XDG_CONFIG_HOME/gamm or ~/.config/gamm]
+    #[arg(long, global = true, value_name = "DIR")]
+    config_dir: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -19,15 +42,43 @@ enum Commands {
     /// Display version information
     Version,
     /// Initialize gamm and install git hooks
-    Init,
+    Init {
+        /// Set the global core.hooksPath to the hooks directory without asking
+        #[arg(long, conflicts_with = "integration")]
+        hooks_path: bool,
+        /// Add gamm to a hook manager in this repository instead of
+        /// installing global hooks: pre-commit, lefthook or husky
+        #[arg(long, value_name = "TOOL")]
+        integration: Option<integration::Integration>,
+        /// Only print the snippet for --integration
+        #[arg(long, requires = "integration")]
+        print: bool,
+    },
     /// Remove gamm git hooks
     Cleanup,
     /// Pre-commit hook: apply git config based on repository URL
     PreCommit {
-        /// Remote repository URL
+        /// Check this remote URL instead of the repository's remotes
         #[arg(long)]
-        repo: String,
+        repo: Option<String>,
+        /// Repository top-level directory, for directory rules
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Prepare-commit-msg hook: add the profile's sign-off and trailers
+    PrepareCommitMsg {
+        /// Commit message file
+        file: PathBuf,
     },
+    /// Commit-msg hook: add the profile's sign-off and trailers
+    CommitMsg {
+        /// Commit message file
+        file: PathBuf,
+    },
+    /// Post-commit hook: restore the global identity saved before switching
+    PostCommit,
+    /// Restore the global identity saved before gamm switched it
+    Restore,
     /// Manage repository configurations
     Repo {
         #[command(subcommand)]
@@ -38,41 +89,335 @@ enum Commands {
         #[command(subcommand)]
         action: ProfileCommands,
     },
+    /// Compile profiles into gitconfig includeIf entries in ~/.gitconfig
+    Sync,
+    /// Manage the `git` shim that injects the profile identity
+    Shim {
+        #[command(subcommand)]
+        action: ShimCommands,
+    },
+    /// Show the profile of the current repository and whether git uses it
+    Status,
+    /// Run gamm from a hook manager
+    Hook {
+        #[command(subcommand)]
+        action: HookCommands,
+    },
+    /// Check the installation and configuration for common problems
+    Doctor {
+        /// Apply the repairs that can't lose anything (hooks, core.hooksPath)
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Inspect and pin the remotes that decide a repository's profile
+    Remote {
+        #[command(subcommand)]
+        action: RemoteCommands,
+    },
+    /// Manage store-wide settings
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommands,
+    },
+    /// Switch to repository-local identity and clean up the global git config
+    Migrate,
+}
+
+impl Commands {
+    /// Whether the command may save config.json, repos.json, settings.json
+    /// or identity.json. Writes are atomic and loads never write, so
+    /// read-only commands don't need the lock. The pre-commit hook takes it
+    /// itself once its prompts are answered, so a commit waiting for input
+    /// doesn't block gamm in other terminals.
+    fn modifies_stores(&self) -> bool {
+        match self {
+            Commands::Version
+            | Commands::PreCommit { .. }
+            | Commands::PrepareCommitMsg { .. }
+            | Commands::CommitMsg { .. }
+            | Commands::Status
+            | Commands::Doctor { .. }
+            | Commands::Remote { .. } => false,
+            Commands::Hook {
+                action: HookCommands::Run { name, .. },
+            } => name == hooks::POST_COMMIT,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum HookCommands {
+    /// Run gamm's part of a git hook (pre-commit, prepare-commit-msg,
+    /// commit-msg or post-commit) in the current repository
+    Run {
+        /// Hook name
+        name: String,
+        /// Arguments git passed to the hook
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
 }
 
 #[derive(Subcommand)]
 enum RepoCommands {
     /// List all configured repositories
     List,
+    /// Register a repository (the current one unless --url or --path is given)
+    Add {
+        /// Remote URL of the repository
+        #[arg(long, conflicts_with = "path")]
+        url: Option<String>,
+        /// Local repository to read the remote URL from
+        #[arg(long)]
+        path: Option<PathBuf>,
+        /// Profile to use for the repository
+        #[arg(long)]
+        profile: String,
+        /// Friendly name (defaults to the last part of the URL)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Assign a registered repository to another profile
+    Set {
+        /// Repository URL or name
+        name: String,
+        /// Profile to use for the repository
+        #[arg(long)]
+        profile: String,
+    },
     /// Delete a repository configuration
     #[command(alias = "rm")]
     Delete {
         /// Repository URL or name to delete (interactive if not provided)
         name: Option<String>,
     },
+    /// Manage wildcard host/organization rules
+    Rule {
+        #[command(subcommand)]
+        action: RuleCommands,
+    },
+    /// Manage directory rules for repos without a (known) remote
+    Dir {
+        #[command(subcommand)]
+        action: DirCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum DirCommands {
+    /// List all directory rules, most specific first
+    List,
+    /// Map repositories under a directory to a profile
+    Add {
+        /// Directory pattern such as `~/work/**` or `/srv/repos/tool`
+        pattern: String,
+        /// Profile name to use for matching repositories
+        profile: String,
+    },
+    /// Delete a directory rule
+    #[command(alias = "rm")]
+    Remove {
+        /// Pattern of the rule to delete
+        pattern: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum RuleCommands {
+    /// List all rules, most specific first
+    List,
+    /// Map repositories matching a pattern to a profile
+    Add {
+        /// Pattern such as `github.com/acme/*`, `gitlab.acme.internal/**` or a bare host
+        pattern: String,
+        /// Profile name to use for matching repositories
+        profile: String,
+    },
+    /// Delete a rule
+    #[command(alias = "rm")]
+    Remove {
+        /// Pattern of the rule to delete
+        pattern: String,
+    },
 }
 
 #[derive(Subcommand)]
 enum ProfileCommands {
     /// List all configured profiles
     List,
-    /// Delete a profile configuration (also removes related repositories)
+    /// Create a profile (interactive if no fields are given)
+    Add {
+        /// Profile name
+        profile: String,
+        #[command(flatten)]
+        fields: command::ProfileFields,
+        /// Add the profile even if its SSH key doesn't exist yet
+        #[arg(long)]
+        force: bool,
+    },
+    /// Change a profile (interactive if no fields are given)
+    Edit {
+        /// Profile name
+        profile: String,
+        #[command(flatten)]
+        fields: command::ProfileFields,
+    },
+    /// Show a single profile
+    Show {
+        /// Profile name
+        profile: String,
+    },
+    /// Rename a profile, updating every repository and rule using it
+    Rename {
+        /// Current profile name
+        old: String,
+        /// New profile name
+        new: String,
+    },
+    /// Delete a profile configuration (also removes related repositories and rules)
     #[command(alias = "rm")]
     Delete {
         /// Profile name to delete (interactive if not provided)
         name: Option<String>,
+        /// Copy the profile's values into the profiles extending it, so
+        /// they keep working after it is gone
+        #[arg(long)]
+        detach: bool,
+    },
+    /// Set an extra git config key on a profile
+    Set {
+        /// Profile name
+        profile: String,
+        /// Git config key (e.g. 'init.defaultBranch')
+        key: String,
+        /// Value
+        value: String,
+        /// Add a value to a multi-valued key instead of replacing it
+        #[arg(long)]
+        add: bool,
+    },
+    /// Remove an extra git config key from a profile
+    Unset {
+        /// Profile name
+        profile: String,
+        /// Git config key
+        key: String,
+    },
+    /// Manage URL rewrites (url.<base>.insteadOf) of a profile
+    Url {
+        #[command(subcommand)]
+        action: UrlCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum UrlCommands {
+    /// List URL rewrites
+    List {
+        /// Only show this profile
+        profile: Option<String>,
+    },
+    /// Rewrite URLs starting with a prefix to start with a base instead
+    Add {
+        /// Profile name
+        profile: String,
+        /// URL the rewritten URLs start with (e.g. 'git@github.com:')
+        base: String,
+        /// Prefix to replace (e.g. 'https://github.com/')
+        #[arg(long)]
+        instead_of: String,
+    },
+    /// Remove URL rewrites with the given base
+    #[command(alias = "rm")]
+    Remove {
+        /// Profile name
+        profile: String,
+        /// Base URL of the rewrite
+        base: String,
+        /// Only remove the rewrite of this prefix
+        #[arg(long)]
+        instead_of: Option<String>,
     },
 }
 
+#[derive(Subcommand)]
+enum RemoteCommands {
+    /// List the remotes of the current repository in priority order
+    List,
+    /// Make a remote decide the profile of the current repository
+    Pin {
+        /// Remote name (e.g. 'upstream')
+        name: String,
+    },
+    /// Remove the pinned remote of the current repository
+    Unpin,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// List all settings
+    List,
+    /// Print a setting
+    Get {
+        /// Setting name (e.g. 'scope')
+        key: String,
+    },
+    /// Change a setting
+    Set {
+        /// Setting name (e.g. 'scope')
+        key: String,
+        /// New value (e.g. 'local' or 'global' for scope)
+        value: String,
+    },
+    /// Move profiles, repositories and rules between config.json/repos.json
+    /// and gamm.toml
+    Convert {
+        /// Target format: toml or json
+        #[arg(long)]
+        to: document::Format,
+    },
+}
+
+#[derive(Subcommand)]
+enum ShimCommands {
+    /// Install gamm as `git` in a directory to put ahead of the real git on PATH
+    Install,
+    /// Remove the `git` shim
+    Uninstall,
+}
+
 fn main() {
+    // Installed as `git`: act as the shim instead of the gamm CLI
+    if shim::invoked_as_git() {
+        shim::run();
+    }
+
     let cli = Cli::parse();
+    if let Some(dir) = cli.config_dir {
+        storage::set_config_dir(dir);
+    }
+
+    // Hold the store lock from load to save, so concurrent commits and edits
+    // can't overwrite each other's changes
+    let _lock = if cli.command.modifies_stores() {
+        match storage::lock() {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
 
     match cli.command {
         Commands::Version => {
             println!("gamm {VERSION}");
         }
-        Commands::Init => {
-            if let Err(e) = command::init() {
+        Commands::Init { hooks_path, integration, print } => {
+            if let Err(e) = command::init(hooks_path, integration, print) {
                 eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
@@ -83,8 +428,11 @@ fn main() {
                 std::process::exit(1);
             }
         }
-        Commands::PreCommit { repo } => {
-            if let Err(e) = command::pre_commit(&repo) {
+        Commands::PreCommit { repo, path } => {
+            // The hook passes empty strings when there is no remote
+            let repo = repo.filter(|r| !r.is_empty());
+            let path = path.filter(|p| !p.as_os_str().is_empty());
+            if let Err(e) = command::pre_commit(repo.as_deref(), path.as_deref()) {
                 eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
@@ -96,12 +444,64 @@ fn main() {
                     std::process::exit(1);
                 }
             }
+            RepoCommands::Add { url, path, profile, name } => {
+                if let Err(e) = command::repo_add(url, path.as_deref(), &profile, name) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            RepoCommands::Set { name, profile } => {
+                if let Err(e) = command::repo_set(&name, &profile) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
             RepoCommands::Delete { name } => {
                 if let Err(e) = command::repo_delete(name) {
                     eprintln!("Error: {}", e);
                     std::process::exit(1);
                 }
             }
+            RepoCommands::Rule { action } => match action {
+                RuleCommands::List => {
+                    if let Err(e) = command::repo_rule_list() {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                RuleCommands::Add { pattern, profile } => {
+                    if let Err(e) = command::repo_rule_add(&pattern, &profile) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                RuleCommands::Remove { pattern } => {
+                    if let Err(e) = command::repo_rule_remove(&pattern) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            },
+            RepoCommands::Dir { action } => match action {
+                DirCommands::List => {
+                    if let Err(e) = command::repo_dir_list() {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                DirCommands::Add { pattern, profile } => {
+                    if let Err(e) = command::repo_dir_add(&pattern, &profile) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                DirCommands::Remove { pattern } => {
+                    if let Err(e) = command::repo_dir_remove(&pattern) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            },
         },
         Commands::Profile { action } => match action {
             ProfileCommands::List => {
@@ -110,12 +510,178 @@ fn main() {
                     std::process::exit(1);
                 }
             }
-            ProfileCommands::Delete { name } => {
-                if let Err(e) = command::profile_delete(name) {
+            ProfileCommands::Add { profile, fields, force } => {
+                if let Err(e) = command::profile_add(&profile, fields, force) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ProfileCommands::Edit { profile, fields } => {
+                if let Err(e) = command::profile_edit(&profile, fields) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ProfileCommands::Show { profile } => {
+                if let Err(e) = command::profile_show(&profile) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ProfileCommands::Rename { old, new } => {
+                if let Err(e) = command::profile_rename(&old, &new) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ProfileCommands::Delete { name, detach } => {
+                if let Err(e) = command::profile_delete(name, detach) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ProfileCommands::Set { profile, key, value, add } => {
+                if let Err(e) = command::profile_set(&profile, &key, &value, add) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ProfileCommands::Unset { profile, key } => {
+                if let Err(e) = command::profile_unset(&profile, &key) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ProfileCommands::Url { action } => match action {
+                UrlCommands::List { profile } => {
+                    if let Err(e) = command::profile_url_list(profile.as_deref()) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                UrlCommands::Add { profile, base, instead_of } => {
+                    if let Err(e) = command::profile_url_add(&profile, &base, &instead_of) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                UrlCommands::Remove { profile, base, instead_of } => {
+                    if let Err(e) = command::profile_url_remove(&profile, &base, instead_of.as_deref()) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            },
+        },
+        Commands::PrepareCommitMsg { file } | Commands::CommitMsg { file } => {
+            if let Err(e) = command::commit_msg(&file) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::PostCommit => {
+            if let Err(e) = command::post_commit() {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Restore => {
+            if let Err(e) = command::restore() {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Status => {
+            if let Err(e) = command::status() {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Hook { action } => match action {
+            HookCommands::Run { name, args } => {
+                if let Err(e) = command::hook_run(&name, &args) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Commands::Doctor { fix } => {
+            if let Err(e) = command::doctor(fix) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Remote { action } => match action {
+            RemoteCommands::List => {
+                if let Err(e) = command::remote_list() {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            RemoteCommands::Pin { name } => {
+                if let Err(e) = command::remote_pin(&name) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            RemoteCommands::Unpin => {
+                if let Err(e) = command::remote_unpin() {
                     eprintln!("Error: {}", e);
                     std::process::exit(1);
                 }
             }
         },
+        Commands::Config { action } => match action {
+            ConfigCommands::List => {
+                if let Err(e) = command::config_list() {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ConfigCommands::Get { key } => {
+                if let Err(e) = command::config_get(&key) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ConfigCommands::Set { key, value } => {
+                if let Err(e) = command::config_set(&key, &value) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ConfigCommands::Convert { to } => {
+                if let Err(e) = command::config_convert(to) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Commands::Sync => {
+            if let Err(e) = command::sync() {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Shim { action } => match action {
+            ShimCommands::Install => {
+                if let Err(e) = command::shim_install() {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            ShimCommands::Uninstall => {
+                if let Err(e) = command::shim_uninstall() {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Commands::Migrate => {
+            if let Err(e) = command::migrate() {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
     }
 }
@@ -0,0 +1,241 @@
+/*
+ * managed.rs
+ * What gamm wrote to a git config scope.
+ *
+ * Applying a profile records what it set in `gamm.managed`, in the same
+ * scope (the repository's .git/config or ~/.gitconfig): the key for keys
+ * gamm owns, and `key<TAB>value` for URL rewrites, whose key gamm shares
+ * with the user's own rewrites. Switching to another profile only removes
+ * recorded entries the new profile doesn't set, so values the user set
+ * themselves (a machine-wide gpg.program or core.sshCommand, say) are
+ * never touched.
+ */
+
+use std::collections::BTreeSet;
+use std::io;
+
+use crate::git;
+use crate::store::{GitConfig, Scope};
+
+/// Config key listing what gamm wrote, one value per entry
+pub const KEY: &str = "gamm.managed";
+
+/// One entry gamm wrote
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Managed {
+    /// Every value of a key
+    Key(String),
+    /// One value of a multi-valued key
+    Value(String, String),
+}
+
+impl Managed {
+    fn parse(record: &str) -> Self {
+        match record.split_once('\t') {
+            Some((key, value)) => Managed::Value(key.to_string(), value.to_string()),
+            None => Managed::Key(record.to_string()),
+        }
+    }
+
+    fn record(&self) -> String {
+        match self {
+            Managed::Key(key) => key.clone(),
+            Managed::Value(key, value) => format!("{}\t{}", key, value),
+        }
+    }
+
+    /// Remove it from `scope`. Returns true if it was set.
+    pub fn unset(&self, scope: Scope) -> io::Result<bool> {
+        match self {
+            Managed::Key(key) if !git::config_get_all(scope, key).is_empty() => {
+                git::config_unset_all(scope, key)?;
+                Ok(true)
+            }
+            Managed::Value(key, value) if git::config_get_all(scope, key).contains(value) => {
+                git::config_unset_value(scope, key, value)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+impl std::fmt::Display for Managed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Managed::Key(key) => write!(f, "{}", key),
+            Managed::Value(key, value) => write!(f, "{} = {}", key, value),
+        }
+    }
+}
+
+/// Entries recorded in `scope`
+pub fn read(scope: Scope) -> Vec<Managed> {
+    git::config_get_all(scope, KEY).iter().map(|r| Managed::parse(r)).collect()
+}
+
+/// What applying `entries` makes gamm own
+pub fn of(entries: &[(String, String)]) -> BTreeSet<Managed> {
+    entries
+        .iter()
+        .map(|(key, value)| {
+            if GitConfig::is_multi_valued(key) {
+                Managed::Value(key.clone(), value.clone())
+            } else {
+                Managed::Key(key.clone())
+            }
+        })
+        .collect()
+}
+
+/// Recorded entries that `entries` don't set, which switching to them removes
+pub fn stale(recorded: &[Managed], entries: &[(String, String)]) -> Vec<Managed> {
+    let own = of(entries);
+    let stale: BTreeSet<&Managed> = recorded.iter().filter(|m| !own.contains(*m)).collect();
+    stale.into_iter().cloned().collect()
+}
+
+/// Remove stale entries from `scope`. Returns the ones that were set.
+pub fn clean(scope: Scope, stale: &[Managed]) -> io::Result<Vec<Managed>> {
+    let mut removed = Vec::new();
+    for entry in stale {
+        if entry.unset(scope)? {
+            removed.push(entry.clone());
+        }
+    }
+    Ok(removed)
+}
+
+/// Remove what gamm recorded in `scope` that `entries` don't set, together
+/// with its record, leaving the rest alone. Returns the entries that were set.
+pub fn tidy(scope: Scope, entries: &[(String, String)]) -> io::Result<Vec<Managed>> {
+    let stale = stale(&read(scope), entries);
+    for entry in &stale {
+        git::config_unset_value(scope, KEY, &entry.record())?;
+    }
+    clean(scope, &stale)
+}
+
+/// Replace the record in `scope` with what `entries` set
+pub fn record(scope: Scope, entries: &[(String, String)]) -> io::Result<()> {
+    git::config_unset_all(scope, KEY)?;
+    for entry in of(entries) {
+        git::config_add(scope, KEY, &entry.record())?;
+    }
+    Ok(())
+}
+
+/// Recorded entries still holding a value one of `profiles` sets (as given by
+/// `GitConfig::entries`), i.e. that nobody changed since gamm wrote them.
+/// `current` reads every value of a key in the scope.
+pub fn unchanged(recorded: &[Managed], current: impl Fn(&str) -> Vec<String>, profiles: &[Vec<(String, String)>]) -> Vec<Managed> {
+    let unchanged: BTreeSet<&Managed> = recorded
+        .iter()
+        .filter(|entry| match entry {
+            Managed::Key(key) => {
+                let values = current(key);
+                !values.is_empty()
+                    && profiles.iter().any(|entries| {
+                        entries.iter().filter(|(k, _)| k == key).map(|(_, v)| v).eq(values.iter())
+                    })
+            }
+            Managed::Value(key, value) => {
+                current(key).contains(value)
+                    && profiles.iter().any(|entries| entries.iter().any(|(k, v)| k == key && v == value))
+            }
+        })
+        .collect();
+    unchanged.into_iter().cloned().collect()
+}
+
+/// Remove an entry gamm recorded in `scope`, together with its record.
+/// Entries gamm didn't record are left alone. Returns true if it was set.
+pub fn forget(scope: Scope, entry: &Managed) -> io::Result<bool> {
+    if !read(scope).contains(entry) {
+        return Ok(false);
+    }
+    git::config_unset_value(scope, KEY, &entry.record())?;
+    entry.unset(scope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect()
+    }
+
+    #[test]
+    fn test_stale() {
+        let entries = pairs(&[
+            ("user.name", "Jane"),
+            ("gpg.format", "ssh"),
+            ("url.git@github.com:.insteadOf", "https://github.com/"),
+        ]);
+        let rewrite = Managed::Value("url.git@github.com:.insteadOf".into(), "https://github.com/".into());
+        assert_eq!(
+            of(&entries).into_iter().collect::<Vec<_>>(),
+            vec![Managed::Key("gpg.format".into()), Managed::Key("user.name".into()), rewrite.clone()]
+        );
+        assert_eq!(Managed::parse(&rewrite.record()), rewrite);
+
+        // Only what gamm recorded is removed; the user's own keys never are
+        let recorded: Vec<Managed> = [
+            "user.name",
+            "user.signingkey",
+            "gpg.program",
+            "user.signingkey",
+            "url.git@github.com:.insteadOf\thttps://github.com/",
+            "url.git@gitlab.com:.insteadOf\thttps://gitlab.com/",
+        ]
+        .iter()
+        .map(|r| Managed::parse(r))
+        .collect();
+        assert_eq!(
+            stale(&recorded, &entries),
+            vec![
+                Managed::Key("gpg.program".into()),
+                Managed::Key("user.signingkey".into()),
+                Managed::Value("url.git@gitlab.com:.insteadOf".into(), "https://gitlab.com/".into()),
+            ]
+        );
+        assert!(stale(&[], &entries).is_empty());
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let profiles = vec![
+            pairs(&[("user.name", "Jane"), ("core.sshCommand", "ssh -i ~/.ssh/id_work -o IdentitiesOnly=yes")]),
+            pairs(&[("user.name", "Jane Doe"), ("url.git@github.com:.insteadOf", "https://github.com/")]),
+        ];
+        let global = pairs(&[
+            ("user.name", "Jane Doe"),
+            ("core.sshCommand", "ssh -i ~/.ssh/mine"),
+            ("gpg.format", "ssh"),
+            ("url.git@github.com:.insteadOf", "https://github.com/"),
+        ]);
+        let current = |key: &str| global.iter().filter(|(k, _)| k == key).map(|(_, v)| v.clone()).collect();
+
+        let recorded: Vec<Managed> = [
+            "user.name",
+            "user.name",
+            "core.sshCommand",
+            "gpg.format",
+            "user.signingkey",
+            "url.git@github.com:.insteadOf\thttps://github.com/",
+        ]
+        .iter()
+        .map(|r| Managed::parse(r))
+        .collect();
+
+        // Values changed since, or that no profile sets, are the user's
+        assert_eq!(
+            unchanged(&recorded, current, &profiles),
+            vec![
+                Managed::Key("user.name".into()),
+                Managed::Value("url.git@github.com:.insteadOf".into(), "https://github.com/".into()),
+            ]
+        );
+    }
+}
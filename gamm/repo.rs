@@ -5,13 +5,28 @@
  * - repo_name: friendly name for the repo
  * - url: remote URL (used for lookup)
  * - commit_by: references the config name in ConfigStore (e.g., "work", "personal")
+ *
+ * Rules map whole hosts or organizations to a profile (see Rule). An exact
+ * repo entry always wins, then the most specific matching rule. Directory
+ * rules (see DirRule) apply when a repository has no remote or no URL
+ * entry or rule matches.
+ *
+ * A repository can have several remotes (e.g. a personal `origin` fork and
+ * the company `upstream`). They are tried in priority order; if two remotes
+ * map to different profiles, the result is a RemoteConflict unless the
+ * deciding remote was chosen explicitly.
  */
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+
+use crate::document::{self, Format};
+use crate::schema::Schema;
+use crate::storage;
+use crate::url::RemoteUrl;
 
 /// A repository entry linking a remote URL to a config profile
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,76 +36,524 @@ pub struct Repo {
     pub commit_by: String,
 }
 
-/// Store for managing repository ownership mappingsz
+/// A wildcard rule mapping a host or organization to a config profile
+///
+/// Patterns are `host[/segment...]` where a segment is a literal, `*` (exactly
+/// one path segment) or `**` (any number of segments). A bare host matches
+/// every repository on that host.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub pattern: String,
+    pub commit_by: String,
+}
+
+impl Rule {
+    /// Host and path segments, with the host lowercased like RemoteUrl
+    fn parts(&self) -> (String, Vec<String>) {
+        let mut parts = self.pattern.trim_end_matches('/').split('/');
+        let host = parts.next().unwrap_or_default().to_lowercase();
+        (host, parts.map(|s| s.to_string()).collect())
+    }
+
+    /// Validate a rule pattern
+    pub fn validate(pattern: &str) -> Result<(), String> {
+        let host = pattern.split('/').next().unwrap_or_default();
+        if host.is_empty() || host.contains('*') {
+            return Err(format!("invalid rule '{}': it must start with a host name", pattern));
+        }
+        if pattern.contains("://") || pattern.contains('@') {
+            return Err(format!(
+                "invalid rule '{}': use host/path form, e.g. github.com/acme/*",
+                pattern
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check whether the rule covers a remote
+    pub fn matches(&self, url: &RemoteUrl) -> bool {
+        let (host, segments) = self.parts();
+        if host != url.authority() {
+            return false;
+        }
+        let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+        // A bare host covers everything on it
+        segments.is_empty() || match_segments(&segments, &url.segments())
+    }
+
+    /// Precedence among matching rules: more literal segments first, then
+    /// single-segment wildcards over `**`. A bare host rule ranks lowest.
+    pub fn specificity(&self) -> (usize, usize) {
+        let (_, segments) = self.parts();
+        let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+        specificity(&segments)
+    }
+}
+
+/// A rule mapping repositories under a directory to a config profile
+///
+/// Patterns are absolute paths (or start with `~/`) whose segments may be
+/// `*` or `**` like Rule. A pattern without wildcards covers the directory
+/// and everything below it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirRule {
+    pub pattern: String,
+    pub commit_by: String,
+}
+
+impl DirRule {
+    /// Validate a directory rule pattern
+    pub fn validate(pattern: &str) -> Result<(), String> {
+        if pattern.starts_with('/') || pattern.starts_with("~/") {
+            Ok(())
+        } else {
+            Err(format!(
+                "invalid directory rule '{}': use an absolute path or one starting with ~/",
+                pattern
+            ))
+        }
+    }
+
+    /// Pattern with `~` expanded and a trailing `**` for wildcard-free patterns
+    pub fn expanded(&self, home: Option<&Path>) -> String {
+        let pattern = self.pattern.trim_end_matches('/');
+        let pattern = match (pattern.strip_prefix("~/"), home) {
+            (Some(rest), Some(home)) => format!("{}/{}", home.display(), rest),
+            _ => pattern.to_string(),
+        };
+        if pattern.contains('*') {
+            pattern
+        } else {
+            format!("{}/**", pattern)
+        }
+    }
+
+    fn segments(pattern: &str) -> Vec<&str> {
+        pattern.split('/').filter(|s| !s.is_empty()).collect()
+    }
+
+    /// Check whether the rule covers a repository at the given top-level path
+    pub fn matches(&self, path: &Path, home: Option<&Path>) -> bool {
+        let pattern = self.expanded(home);
+        let path = path.to_string_lossy();
+        match_segments(&Self::segments(&pattern), &Self::segments(&path))
+    }
+
+    /// Precedence among matching directory rules, like Rule::specificity.
+    /// Counted on the expanded pattern, so `~/work/x` and the same path
+    /// spelled out rank the same.
+    pub fn specificity(&self, home: Option<&Path>) -> (usize, usize) {
+        specificity(&Self::segments(&self.expanded(home)))
+    }
+}
+
+/// Count literal and single-wildcard segments of a pattern
+fn specificity(segments: &[&str]) -> (usize, usize) {
+    let literals = segments.iter().filter(|s| **s != "*" && **s != "**").count();
+    let singles = segments.iter().filter(|s| **s == "*").count();
+    (literals, singles)
+}
+
+/// Glob-match path segments, where `*` is one segment and `**` any number
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            match_segments(&pattern[1..], path)
+                || (!path.is_empty() && match_segments(pattern, &path[1..]))
+        }
+        (Some(p), Some(s)) => (*p == "*" || p == s) && match_segments(&pattern[1..], &path[1..]),
+        _ => false,
+    }
+}
+
+/// How a repository URL was resolved to a profile
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchSource {
+    /// A repo entry with exactly this URL
+    Exact,
+    /// A wildcard rule, identified by its pattern
+    Rule(String),
+    /// A directory rule, identified by its pattern
+    Dir(String),
+}
+
+impl std::fmt::Display for MatchSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchSource::Exact => write!(f, "exact entry"),
+            MatchSource::Rule(pattern) => write!(f, "rule {}", pattern),
+            MatchSource::Dir(pattern) => write!(f, "directory rule {}", pattern),
+        }
+    }
+}
+
+/// Result of resolving a URL to a profile
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    pub commit_by: &'a str,
+    pub source: MatchSource,
+}
+
+/// A named remote of a repository
+#[derive(Debug, Clone, PartialEq)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+}
+
+/// Put remotes in the order they are tried: names listed in `priority`
+/// first (in list order), then `origin`, then the rest by name
+pub fn order_remotes(remotes: Vec<(String, String)>, priority: &[String]) -> Vec<Remote> {
+    let mut remotes: Vec<Remote> = remotes
+        .into_iter()
+        .map(|(name, url)| Remote { name, url })
+        .collect();
+    remotes.sort_by_key(|r| {
+        let rank = priority.iter().position(|p| *p == r.name).unwrap_or(priority.len());
+        (rank, r.name != "origin", r.name.clone())
+    });
+    remotes
+}
+
+/// Result of resolving a repository from its remotes
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteMatch<'a> {
+    /// The remote that decided, if a remote decided at all
+    pub remote: Option<Remote>,
+    pub found: Match<'a>,
+}
+
+/// Remotes of one repository that map to different profiles
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteConflict {
+    /// (remote, profile) for every remote that matched, in priority order
+    pub candidates: Vec<(Remote, String)>,
+}
+
+impl std::fmt::Display for RemoteConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "remotes of this repository map to different profiles:")?;
+        for (remote, profile) in &self.candidates {
+            writeln!(f, "  {} ({}) -> {}", remote.name, remote.url, profile)?;
+        }
+        write!(
+            f,
+            "Pin the deciding remote with `gamm remote pin <name>` or set `gamm config set remote-priority <names>`."
+        )
+    }
+}
+
+impl std::error::Error for RemoteConflict {}
+
+/// Store for managing repository ownership mappings
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct RepoStore {
     /// Maps remote URL -> Repo
+    #[serde(default)]
     repos: HashMap<String, Repo>,
+    /// Wildcard host/organization rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rules: Vec<Rule>,
+    /// Directory rules, used when no URL entry or rule applies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dir_rules: Vec<DirRule>,
+    /// Whether loading re-keyed the entries, so saving backs up the file
+    #[serde(skip)]
+    rekeyed: bool,
+    /// What re-keying on load merged, reported when the store is saved
+    #[serde(skip)]
+    notes: Vec<String>,
+    /// Entries re-keying found for a repository already listed with another
+    /// profile. Saving is refused until the user picks one.
+    #[serde(skip)]
+    conflicts: Vec<Repo>,
 }
 
+/// Layout history of repos.json. Version 2 only added the envelope.
+const SCHEMA: Schema = Schema {
+    file: "repos.json",
+    current: 2,
+    migrations: &[Ok],
+};
+
 impl RepoStore {
-    /// Get the config directory path (~/.config/gam or platform equivalent)
-    pub fn config_dir() -> Option<PathBuf> {
-        dirs::config_dir().map(|p| p.join("gamm"))
-    }
+    /// File name in the config dir
+    pub const FILE: &'static str = "repos.json";
+
+    /// Top-level keys of gamm.toml this store owns
+    const KEYS: &'static [&'static str] = &["repos", "rules", "dir_rules"];
 
-    /// Get the repos file path (~/.config/gam/repos.json)
+    /// Get the repos file path (~/.config/gamm/repos.json)
     pub fn repos_path() -> Option<PathBuf> {
-        Self::config_dir().map(|p| p.join("repos.json"))
+        storage::config_dir().map(|p| p.join(Self::FILE))
     }
 
     /// Create a new empty store
     pub fn new() -> Self {
         Self {
             repos: HashMap::new(),
+            rules: Vec::new(),
+            dir_rules: Vec::new(),
+            rekeyed: false,
+            notes: Vec::new(),
+            conflicts: Vec::new(),
         }
     }
 
-    /// Load store from the default repos file, or create new if it doesn't exist
+    /// Load store from the config dir, or create new if it doesn't exist
     pub fn load() -> io::Result<Self> {
-        let path = Self::repos_path().ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, "Could not find config directory")
-        })?;
-
-        if path.exists() {
-            let contents = fs::read_to_string(&path)?;
-            serde_json::from_str(&contents)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-        } else {
-            Ok(Self::new())
+        Self::load_from(&storage::require_config_dir()?)
+    }
+
+    /// Load store from repos.json or gamm.toml in `dir`
+    pub fn load_from(dir: &Path) -> io::Result<Self> {
+        storage::recover(dir)?;
+        let store = match Format::detect_in(dir) {
+            Format::Toml => Some(document::load::<Self>(&dir.join(document::FILE))?),
+            Format::Json => SCHEMA.load::<Self>(&dir.join(Self::FILE))?,
+        };
+        match store {
+            Some(mut store) => {
+                // One-time migration from raw URL keys to canonical keys,
+                // written by the next save
+                if store.repos.keys().any(|k| *k != RemoteUrl::key_for(&store.repos[k].url)) {
+                    store.notes = store.normalize_keys();
+                    store.rekeyed = true;
+                }
+
+                Ok(store)
+            }
+            None => Ok(Self::new()),
         }
     }
 
-    /// Save store to the default repos file
-    pub fn save(&self) -> io::Result<()> {
-        let dir = Self::config_dir().ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, "Could not find config directory")
-        })?;
-        let path = Self::repos_path().unwrap();
+    /// Before the file in `dir` is overwritten, back it up if it has an
+    /// older version or its keys were rewritten on load
+    pub fn backup_in(&self, dir: &Path, format: Format) -> io::Result<()> {
+        let path = match format {
+            Format::Json => dir.join(Self::FILE),
+            Format::Toml => dir.join(document::FILE),
+        };
+        let (upgraded, current) = match format {
+            Format::Json => (SCHEMA.backup(&path)?, SCHEMA.current),
+            Format::Toml => (document::backup(&path)?, document::SCHEMA.current),
+        };
+        if upgraded.is_none() && self.rekeyed && path.exists() {
+            let backup = Schema::copy_to_backup(&path, current)?;
+            eprintln!(
+                "gamm: {}: re-keyed repositories by URL (previous file saved as {})",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                backup.display()
+            );
+        }
+        Ok(())
+    }
 
-        // Create directory if it doesn't exist
-        fs::create_dir_all(&dir)?;
+    /// What loading changed that saving writes
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
 
-        let contents = serde_json::to_string_pretty(self)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        fs::write(&path, contents)
+    /// Entries for a repository that is also listed with another profile,
+    /// as (entry, the entry kept). Empty unless the file was edited by hand
+    /// or written by an older gamm.
+    pub fn conflicts(&self) -> Vec<(&Repo, &Repo)> {
+        self.conflicts
+            .iter()
+            .filter_map(|repo| Some((repo, self.repos.get(&RemoteUrl::key_for(&repo.url))?)))
+            .collect()
+    }
+
+    /// Contents of repos.json
+    pub fn to_json(&self) -> io::Result<String> {
+        SCHEMA.to_string(self)
+    }
+
+    /// Merge the repositories and rules into a parsed gamm.toml
+    pub fn to_document(&self, doc: &mut DocumentMut, path: &Path) -> io::Result<()> {
+        document::update(doc, path, self, Self::KEYS)
     }
 
     /// Add a new repo to the store
-    /// The URL is used as the key for lookups
+    /// The canonical form of the URL is used as the key for lookups
     pub fn add(&mut self, repo: Repo) {
-        self.repos.insert(repo.url.clone(), repo);
+        let key = RemoteUrl::key_for(&repo.url);
+        self.conflicts.retain(|r| RemoteUrl::key_for(&r.url) != key);
+        self.repos.insert(key, repo);
+    }
+
+    /// Look up who owns the repo by remote URL: an exact entry wins, then
+    /// the most specific rule
+    pub fn lookup(&self, url: &str) -> Option<Match<'_>> {
+        if let Some(repo) = self.repos.get(&RemoteUrl::key_for(url)) {
+            return Some(Match {
+                commit_by: &repo.commit_by,
+                source: MatchSource::Exact,
+            });
+        }
+
+        self.matching_rule(url).map(|rule| Match {
+            commit_by: &rule.commit_by,
+            source: MatchSource::Rule(rule.pattern.clone()),
+        })
+    }
+
+    /// Resolve a repository from its remote URL and/or top-level path.
+    /// URL entries and rules come first; directory rules are the fallback.
+    pub fn resolve(&self, url: Option<&str>, path: Option<&Path>) -> Option<Match<'_>> {
+        if let Some(found) = url.and_then(|u| self.lookup(u)) {
+            return Some(found);
+        }
+
+        let home = dirs::home_dir();
+        self.matching_dir_rule(path?, home.as_deref()).map(|rule| Match {
+            commit_by: &rule.commit_by,
+            source: MatchSource::Dir(rule.pattern.clone()),
+        })
+    }
+
+    /// Resolve a repository from all of its remotes (in priority order) and
+    /// its top-level path. The first remote that matches decides, but if
+    /// another remote maps to a different profile that is a conflict unless
+    /// the deciding remote is listed in `priority`. Directory rules apply
+    /// only when no remote matches.
+    pub fn resolve_remotes(
+        &self,
+        remotes: &[Remote],
+        path: Option<&Path>,
+        priority: &[String],
+    ) -> Result<Option<RemoteMatch<'_>>, RemoteConflict> {
+        let matches: Vec<(&Remote, Match<'_>)> = remotes
+            .iter()
+            .filter_map(|remote| self.lookup(&remote.url).map(|found| (remote, found)))
+            .collect();
+
+        let Some((remote, found)) = matches.first() else {
+            return Ok(self.resolve(None, path).map(|found| RemoteMatch { remote: None, found }));
+        };
+
+        let disagree = matches.iter().any(|(_, m)| m.commit_by != found.commit_by);
+        if disagree && !priority.contains(&remote.name) {
+            return Err(RemoteConflict {
+                candidates: matches
+                    .iter()
+                    .map(|(r, m)| ((*r).clone(), m.commit_by.to_string()))
+                    .collect(),
+            });
+        }
+
+        Ok(Some(RemoteMatch {
+            remote: Some((*remote).clone()),
+            found: found.clone(),
+        }))
+    }
+
+    /// Find the most specific directory rule covering a repository path
+    pub fn matching_dir_rule(&self, path: &Path, home: Option<&Path>) -> Option<&DirRule> {
+        self.dir_rules_ranked(home)
+            .into_iter()
+            .find(|rule| rule.matches(path, home))
+    }
+
+    /// All directory rules, most specific first
+    pub fn dir_rules_by_precedence(&self) -> Vec<&DirRule> {
+        self.dir_rules_ranked(dirs::home_dir().as_deref())
+    }
+
+    fn dir_rules_ranked(&self, home: Option<&Path>) -> Vec<&DirRule> {
+        let mut rules: Vec<_> = self.dir_rules.iter().collect();
+        rules.sort_by(|a, b| {
+            b.specificity(home)
+                .cmp(&a.specificity(home))
+                .then_with(|| a.pattern.cmp(&b.pattern))
+        });
+        rules
+    }
+
+    /// Add or replace a directory rule
+    pub fn add_dir_rule(&mut self, rule: DirRule) {
+        self.dir_rules.retain(|r| r.pattern != rule.pattern);
+        self.dir_rules.push(rule);
     }
 
-    /// Look up who owns the repo by remote URL
-    /// Returns the commit_by (config profile name) if found
-    pub fn lookup_owner_by_url(&self, url: &str) -> Option<&str> {
-        self.repos.get(url).map(|r| r.commit_by.as_str())
+    /// Remove a directory rule by its pattern
+    pub fn remove_dir_rule(&mut self, pattern: &str) -> Option<DirRule> {
+        let index = self.dir_rules.iter().position(|r| r.pattern == pattern)?;
+        Some(self.dir_rules.remove(index))
     }
 
-    /// Remove a repo by its URL
+    /// Find the most specific rule covering a URL, ignoring exact entries
+    pub fn matching_rule(&self, url: &str) -> Option<&Rule> {
+        let url = RemoteUrl::parse(url)?;
+
+        self.rules_by_precedence()
+            .into_iter()
+            .find(|rule| rule.matches(&url))
+    }
+
+    /// Add or replace a rule
+    pub fn add_rule(&mut self, rule: Rule) {
+        self.rules.retain(|r| r.pattern != rule.pattern);
+        self.rules.push(rule);
+    }
+
+    /// Remove a rule by its pattern
+    pub fn remove_rule(&mut self, pattern: &str) -> Option<Rule> {
+        let index = self.rules.iter().position(|r| r.pattern == pattern)?;
+        Some(self.rules.remove(index))
+    }
+
+    /// All rules, most specific first. Ties are broken by pattern so the
+    /// result never depends on insertion order.
+    pub fn rules_by_precedence(&self) -> Vec<&Rule> {
+        let mut rules: Vec<_> = self.rules.iter().collect();
+        rules.sort_by(|a, b| {
+            b.specificity()
+                .cmp(&a.specificity())
+                .then_with(|| a.pattern.cmp(&b.pattern))
+        });
+        rules
+    }
+
+    /// Find a repo by its friendly name or its URL (any equivalent form)
+    pub fn find(&self, name_or_url: &str) -> Option<&Repo> {
+        self.repos
+            .get(&RemoteUrl::key_for(name_or_url))
+            .or_else(|| self.repos.values().find(|r| r.repo_name == name_or_url))
+    }
+
+    /// Remove a repo by its URL (any equivalent form)
     pub fn remove_by_url(&mut self, url: &str) -> Option<Repo> {
-        self.repos.remove(url)
+        let key = RemoteUrl::key_for(url);
+        self.conflicts.retain(|r| RemoteUrl::key_for(&r.url) != key);
+        self.repos.remove(&key)
+    }
+
+    /// Re-key entries by canonical URL, merging duplicates left by older
+    /// versions that keyed by the raw URL. Returns a note per merged entry.
+    ///
+    /// When duplicates disagree on the profile, the entry whose stored key
+    /// sorts first is used for lookups and the others are kept in
+    /// `conflicts`, so nothing is dropped without the user choosing.
+    pub fn normalize_keys(&mut self) -> Vec<String> {
+        let mut old: Vec<(String, Repo)> = self.repos.drain().collect();
+        old.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut notes = Vec::new();
+        for (old_key, repo) in old {
+            let key = RemoteUrl::key_for(&repo.url);
+            match self.repos.get(&key) {
+                Some(kept) if kept.commit_by == repo.commit_by => {
+                    notes.push(format!("merged '{}' into '{}'", old_key, kept.url));
+                }
+                Some(_) => self.conflicts.push(repo),
+                None => {
+                    self.repos.insert(key, repo);
+                }
+            }
+        }
+        notes
     }
 
     /// Iterate over all repos
@@ -98,12 +561,32 @@ impl RepoStore {
         self.repos.iter()
     }
 
-    /// Find all repos owned by a specific config profile
-    pub fn find_by_owner(&self, commit_by: &str) -> Vec<&Repo> {
-        self.repos
-            .values()
-            .filter(|r| r.commit_by == commit_by)
-            .collect()
+    /// Point every repo, rule and directory rule using profile `old` at
+    /// `new`. Returns how many were changed.
+    pub fn rename_owner(&mut self, old: &str, new: &str) -> usize {
+        let owners = self
+            .repos
+            .values_mut()
+            .map(|r| &mut r.commit_by)
+            .chain(self.rules.iter_mut().map(|r| &mut r.commit_by))
+            .chain(self.dir_rules.iter_mut().map(|r| &mut r.commit_by));
+
+        let mut changed = 0;
+        for owner in owners.filter(|o| o.as_str() == old) {
+            *owner = new.to_string();
+            changed += 1;
+        }
+        changed
+    }
+
+    /// Remove every repo, rule and directory rule using a profile. Returns
+    /// how many were removed.
+    pub fn remove_owner(&mut self, owner: &str) -> usize {
+        let before = self.repos.len() + self.rules.len() + self.dir_rules.len();
+        self.repos.retain(|_, r| r.commit_by != owner);
+        self.rules.retain(|r| r.commit_by != owner);
+        self.dir_rules.retain(|r| r.commit_by != owner);
+        before - (self.repos.len() + self.rules.len() + self.dir_rules.len())
     }
 }
 
@@ -111,6 +594,10 @@ impl RepoStore {
 mod tests {
     use super::*;
 
+    fn owner<'a>(store: &'a RepoStore, url: &str) -> Option<&'a str> {
+        store.resolve(Some(url), None).map(|m| m.commit_by)
+    }
+
     fn sample_repo() -> Repo {
         Repo {
             repo_name: "gam".into(),
@@ -120,15 +607,254 @@ mod tests {
     }
 
     #[test]
-    fn test_lookup_owner_by_url() {
+    fn test_resolve_by_url() {
+        let mut store = RepoStore::new();
+        store.add(sample_repo());
+
+        assert_eq!(owner(&store, "git@github.com:9bany/gam.git"), Some("personal"));
+        assert!(owner(&store, "nonexistent").is_none());
+    }
+
+    fn rule(pattern: &str, commit_by: &str) -> Rule {
+        Rule {
+            pattern: pattern.into(),
+            commit_by: commit_by.into(),
+        }
+    }
+
+    #[test]
+    fn test_rule_precedence() {
+        let mut store = RepoStore::new();
+        store.add_rule(rule("github.com", "personal"));
+        store.add_rule(rule("github.com/acme/**", "contractor"));
+        store.add_rule(rule("github.com/acme/*", "work"));
+        store.add(Repo {
+            repo_name: "special".into(),
+            url: "git@github.com:acme/special.git".into(),
+            commit_by: "oss".into(),
+        });
+
+        // Exact entry beats every rule
+        let m = store.lookup("git@github.com:acme/special.git").unwrap();
+        assert_eq!(m.commit_by, "oss");
+        assert_eq!(m.source, MatchSource::Exact);
+
+        // `*` beats `**` at the same depth
+        let m = store.lookup("https://github.com/acme/api.git").unwrap();
+        assert_eq!(m.commit_by, "work");
+        assert_eq!(m.source, MatchSource::Rule("github.com/acme/*".into()));
+
+        // Nested groups only match `**`
+        assert_eq!(owner(&store, "git@github.com:acme/team/api.git"), Some("contractor"));
+
+        // Anything else on the host falls back to the host rule
+        assert_eq!(owner(&store, "git@GitHub.com:9bany/gam.git"), Some("personal"));
+        assert!(store.lookup("git@gitlab.com:acme/api.git").is_none());
+    }
+
+    #[test]
+    fn test_lookup_ignores_protocol_and_case() {
+        let mut store = RepoStore::new();
+        store.add(sample_repo());
+
+        assert_eq!(owner(&store, "https://github.com/9bany/gam"), Some("personal"));
+        assert_eq!(owner(&store, "ssh://git@GitHub.com/9bany/gam.git/"), Some("personal"));
+
+        assert!(store.remove_by_url("https://github.com/9bany/gam/").is_some());
+        assert!(owner(&store, "git@github.com:9bany/gam.git").is_none());
+    }
+
+    #[test]
+    fn test_normalize_keys_merges_duplicates() {
+        let json = r#"{"repos":{
+            "git@github.com:org/app.git":{"repo_name":"app","url":"git@github.com:org/app.git","commit_by":"work"},
+            "https://github.com/org/app":{"repo_name":"app","url":"https://github.com/org/app","commit_by":"work"},
+            "https://github.com/org/lib":{"repo_name":"lib","url":"https://github.com/org/lib","commit_by":"work"},
+            "ssh://git@github.com/org/lib.git":{"repo_name":"lib","url":"ssh://git@github.com/org/lib.git","commit_by":"personal"}
+        }}"#;
+        let mut store: RepoStore = serde_json::from_str(json).unwrap();
+
+        let notes = store.normalize_keys();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(store.iter().count(), 2);
+        assert_eq!(owner(&store, "https://github.com/org/app.git"), Some("work"));
+        // Conflicting duplicates use the entry whose old key sorts first,
+        // and keep the other until the user picks a profile
+        assert_eq!(owner(&store, "git@github.com:org/lib"), Some("work"));
+        let conflicts = store.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!((conflicts[0].0.commit_by.as_str(), conflicts[0].1.commit_by.as_str()), ("personal", "work"));
+        let mut picked = store.find("lib").unwrap().clone();
+        picked.commit_by = "personal".into();
+        store.add(picked);
+        assert!(store.conflicts().is_empty());
+
+        // Already canonical: nothing to do
+        assert!(store.normalize_keys().is_empty());
+    }
+
+    #[test]
+    fn test_rule_validation_and_replacement() {
+        assert!(Rule::validate("github.com/acme/*").is_ok());
+        assert!(Rule::validate("*/acme").is_err());
+        assert!(Rule::validate("https://github.com/acme").is_err());
+
+        let mut store = RepoStore::new();
+        store.add_rule(rule("github.com/acme/*", "work"));
+        store.add_rule(rule("github.com/acme/*", "other"));
+        assert_eq!(store.rules_by_precedence().len(), 1);
+        assert_eq!(store.remove_rule("github.com/acme/*").unwrap().commit_by, "other");
+        assert!(store.remove_rule("github.com/acme/*").is_none());
+    }
+
+    #[test]
+    fn test_dir_rules() {
+        let home = Path::new("/home/me");
+        let mut store = RepoStore::new();
+        store.add_dir_rule(DirRule {
+            pattern: "~/work/**".into(),
+            commit_by: "work".into(),
+        });
+        store.add_dir_rule(DirRule {
+            pattern: "~/work/oss/*".into(),
+            commit_by: "personal".into(),
+        });
+        store.add_dir_rule(DirRule {
+            pattern: "/srv/repos/tool".into(),
+            commit_by: "ops".into(),
+        });
+        store.add_dir_rule(DirRule {
+            pattern: "/home/me/work/oss".into(),
+            commit_by: "oss".into(),
+        });
+
+        let owner = |p: &str| {
+            store
+                .matching_dir_rule(Path::new(p), Some(home))
+                .map(|r| r.commit_by.as_str())
+        };
+        assert_eq!(owner("/home/me/work/api"), Some("work"));
+        assert_eq!(owner("/home/me/work/team/api"), Some("work"));
+        assert_eq!(owner("/home/me/work/oss/lib"), Some("personal"));
+        assert_eq!(owner("/srv/repos/tool"), Some("ops"));
+        assert_eq!(owner("/srv/repos/tool/sub"), Some("ops"));
+        assert_eq!(owner("/srv/repos/toolbox"), None);
+        assert_eq!(owner("/home/me/private"), None);
+
+        // `~` counts as the segments it expands to, so `~/work/oss/*` is
+        // more specific than `/home/me/work/oss`
+        assert_eq!(owner("/home/me/work/oss"), Some("oss"));
+
+        assert!(DirRule::validate("~/work/**").is_ok());
+        assert!(DirRule::validate("work/**").is_err());
+    }
+
+    #[test]
+    fn test_resolve_prefers_url_over_dir_rules() {
         let mut store = RepoStore::new();
         store.add(sample_repo());
+        store.add_dir_rule(DirRule {
+            pattern: "/src/**".into(),
+            commit_by: "work".into(),
+        });
+
+        let path = Path::new("/src/gam");
+        let found = store.resolve(Some("git@github.com:9bany/gam.git"), Some(path)).unwrap();
+        assert_eq!(found.commit_by, "personal");
+
+        // Unknown remote or no remote at all: fall back to the directory
+        let found = store.resolve(Some("git@github.com:x/y.git"), Some(path)).unwrap();
+        assert_eq!(found.source, MatchSource::Dir("/src/**".into()));
+        assert_eq!(store.resolve(None, Some(path)).unwrap().commit_by, "work");
+        assert!(store.resolve(None, None).is_none());
+    }
+
+    #[test]
+    fn test_find_by_name_or_url() {
+        let mut store = RepoStore::new();
+        store.add(sample_repo());
+
+        assert_eq!(store.find("gam").unwrap().commit_by, "personal");
+        assert_eq!(store.find("https://github.com/9bany/gam").unwrap().repo_name, "gam");
+        assert!(store.find("other").is_none());
+    }
+
+    #[test]
+    fn test_rename_and_remove_owner() {
+        let mut store = RepoStore::new();
+        store.add(sample_repo());
+        store.add_rule(rule("github.com/acme/*", "personal"));
+        store.add_rule(rule("gitlab.com/acme/*", "work"));
+        store.add_dir_rule(DirRule {
+            pattern: "~/src/**".into(),
+            commit_by: "personal".into(),
+        });
+
+        assert_eq!(store.rename_owner("personal", "home"), 3);
+        assert!(store.iter().all(|(_, repo)| repo.commit_by == "home"));
+        assert_eq!(store.matching_rule("git@github.com:acme/api.git").unwrap().commit_by, "home");
+        assert_eq!(store.matching_rule("git@gitlab.com:acme/api.git").unwrap().commit_by, "work");
+        assert_eq!(store.dir_rules_by_precedence()[0].commit_by, "home");
+
+        assert_eq!(store.remove_owner("home"), 3);
+        assert_eq!(store.iter().count(), 0);
+        assert_eq!(store.rules_by_precedence().len(), 1);
+        assert!(store.dir_rules_by_precedence().is_empty());
+    }
+
+    fn remotes(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|(n, u)| (n.to_string(), u.to_string())).collect()
+    }
+
+    #[test]
+    fn test_order_remotes() {
+        let list = remotes(&[("zz", "a"), ("upstream", "b"), ("origin", "c"), ("backup", "d")]);
+
+        let names = |r: Vec<Remote>| r.into_iter().map(|r| r.name).collect::<Vec<_>>();
+        assert_eq!(names(order_remotes(list.clone(), &[])), vec!["origin", "backup", "upstream", "zz"]);
+        assert_eq!(
+            names(order_remotes(list, &["upstream".into()])),
+            vec!["upstream", "origin", "backup", "zz"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_remotes() {
+        let mut store = RepoStore::new();
+        store.add_rule(rule("github.com/acme/*", "work"));
+        store.add_rule(rule("github.com/jane/*", "personal"));
+
+        let fork = order_remotes(
+            remotes(&[
+                ("origin", "git@github.com:jane/api.git"),
+                ("upstream", "git@github.com:acme/api.git"),
+            ]),
+            &[],
+        );
+
+        // origin and upstream disagree and nothing was chosen explicitly
+        let conflict = store.resolve_remotes(&fork, None, &[]).unwrap_err();
+        assert_eq!(conflict.candidates.len(), 2);
+        assert_eq!(conflict.candidates[0].1, "personal");
+
+        // Listing upstream in the priority settles it
+        let priority = vec!["upstream".to_string()];
+        let ordered = order_remotes(
+            fork.iter().map(|r| (r.name.clone(), r.url.clone())).collect(),
+            &priority,
+        );
+        let found = store.resolve_remotes(&ordered, None, &priority).unwrap().unwrap();
+        assert_eq!(found.found.commit_by, "work");
+        assert_eq!(found.remote.unwrap().name, "upstream");
 
-        let owner = store.lookup_owner_by_url("git@github.com:9bany/gam.git");
-        assert_eq!(owner, Some("personal"));
+        // A remote other than origin is matched too
+        let single = order_remotes(remotes(&[("company", "https://github.com/acme/web")]), &[]);
+        let found = store.resolve_remotes(&single, None, &[]).unwrap().unwrap();
+        assert_eq!(found.found.commit_by, "work");
 
-        let owner = store.lookup_owner_by_url("nonexistent");
-        assert!(owner.is_none());
+        // Nothing matches
+        let unknown = order_remotes(remotes(&[("origin", "git@gitlab.com:x/y.git")]), &[]);
+        assert!(store.resolve_remotes(&unknown, None, &[]).unwrap().is_none());
     }
 
     #[test]
@@ -0,0 +1,277 @@
+/*
+ * schema.rs
+ * Versioned layout of config.json and repos.json.
+ *
+ * Both files are written as an envelope with a top-level `version`:
+ *
+ *   { "version": 2, "profiles": { ... } }
+ *
+ * Files without one are version 1 (the layout before envelopes). On load an
+ * older file runs through the migrations one version at a time, in memory;
+ * the next save copies the original to `<file>.v<N>.bak` (or, when that
+ * exists, `<file>.v<N>.<timestamp>.bak`, so earlier backups are kept) and
+ * writes the upgraded file, so commands that only read never write. A file from a
+ * newer gamm is refused and left untouched, because rewriting it would drop
+ * whatever that version added.
+ */
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Upgrade the data of one version to the next
+pub type Migration = fn(Value) -> Result<Value, String>;
+
+/// Layout history of one file
+pub struct Schema {
+    /// File name, for messages
+    pub file: &'static str,
+    /// Version this gamm writes
+    pub current: u32,
+    /// `migrations[i]` upgrades version i + 1 to i + 2
+    pub migrations: &'static [Migration],
+}
+
+/// Version written on disk
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    version: u32,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+impl Schema {
+    /// Version of a parsed file; files without a numeric `version` are 1
+    pub fn version_of(value: &Value) -> Result<u32, String> {
+        match value.get("version") {
+            Some(Value::Number(n)) => n
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .filter(|v| *v >= 1)
+                .ok_or_else(|| format!("invalid version {}", n)),
+            _ => Ok(1),
+        }
+    }
+
+    /// Refuse a file written by a newer gamm
+    pub fn check_version(&self, version: u32) -> Result<(), String> {
+        if version > self.current {
+            return Err(format!(
+                "version {} is newer than this gamm understands (up to {}); upgrade gamm (the file was left untouched)",
+                version, self.current
+            ));
+        }
+        Ok(())
+    }
+
+    /// Bring parsed file contents to the current version. Returns the data
+    /// without the `version` key and the version the file had.
+    pub fn upgrade(&self, mut value: Value) -> Result<(Value, u32), String> {
+        let from = Self::version_of(&value)?;
+        self.check_version(from)?;
+
+        if from > 1
+            && let Some(map) = value.as_object_mut()
+        {
+            map.remove("version");
+        }
+        for (step, migrate) in self.migrations.iter().enumerate().skip(from as usize - 1) {
+            value = migrate(value)
+                .map_err(|e| format!("can't upgrade from version {} to {}: {}", step + 1, step + 2, e))?;
+        }
+
+        Ok((value, from))
+    }
+
+    /// Path of the backup taken before upgrading from `version`
+    pub fn backup_path(path: &Path, version: u32) -> PathBuf {
+        Self::with_suffix(path, &format!(".v{}.bak", version))
+    }
+
+    fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        path.with_file_name(name)
+    }
+
+    /// Copy `path` to a new backup taken before upgrading from `version`:
+    /// `backup_path`, or a timestamped name when that exists, so an earlier
+    /// backup is never overwritten
+    pub fn copy_to_backup(path: &Path, version: u32) -> io::Result<PathBuf> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mut n = 0;
+        loop {
+            let backup = match n {
+                0 => Self::backup_path(path, version),
+                1 => Self::with_suffix(path, &format!(".v{}.{}.bak", version, secs)),
+                _ => Self::with_suffix(path, &format!(".v{}.{}-{}.bak", version, secs, n - 1)),
+            };
+            let mut file = match File::options().write(true).create_new(true).open(&backup) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    n += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let copied = (|| {
+                file.set_permissions(fs::metadata(path)?.permissions())?;
+                io::copy(&mut File::open(path)?, &mut file)?;
+                file.sync_all()
+            })();
+            return match copied {
+                Ok(()) => Ok(backup),
+                Err(e) => {
+                    let _ = fs::remove_file(&backup);
+                    Err(e)
+                }
+            };
+        }
+    }
+
+    /// Read a file, upgrading an older version in memory. Returns None when
+    /// the file doesn't exist.
+    pub fn load<T: DeserializeOwned>(&self, path: &Path) -> io::Result<Option<T>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+        let contents = fs::read_to_string(path)?;
+        let value: Value = serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+        let (value, from) = self.upgrade(value).map_err(invalid)?;
+
+        // Parse current files again from the text, so errors have a line and column
+        let data: T = if from == self.current {
+            serde_json::from_str(&contents)
+        } else {
+            serde_json::from_value(value)
+        }
+        .map_err(|e| invalid(e.to_string()))?;
+
+        Ok(Some(data))
+    }
+
+    /// Before `path` is overwritten, copy it to its backup if it has an older
+    /// version. Returns the backup's path if one was made.
+    pub fn backup(&self, path: &Path) -> io::Result<Option<PathBuf>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let from = serde_json::from_str(&contents)
+            .ok()
+            .and_then(|value| Self::version_of(&value).ok())
+            .unwrap_or(self.current);
+        self.backup_from(path, from)
+    }
+
+    /// Like `backup`, for a file whose version was read by the caller
+    pub fn backup_from(&self, path: &Path, from: u32) -> io::Result<Option<PathBuf>> {
+        if from >= self.current || !path.exists() {
+            return Ok(None);
+        }
+
+        let backup = Self::copy_to_backup(path, from)?;
+        eprintln!(
+            "gamm: {}: upgraded from version {} to {} (previous file saved as {})",
+            self.file,
+            from,
+            self.current,
+            backup.display()
+        );
+        Ok(Some(backup))
+    }
+
+    /// Serialize data inside the envelope of the current version
+    pub fn to_string<T: Serialize>(&self, data: &T) -> io::Result<String> {
+        let envelope = Envelope {
+            version: self.current,
+            data,
+        };
+        serde_json::to_string_pretty(&envelope).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn nest(value: Value) -> Result<Value, String> {
+        Ok(json!({ "items": value }))
+    }
+
+    fn rename(mut value: Value) -> Result<Value, String> {
+        let items = value.as_object_mut().and_then(|m| m.remove("items")).ok_or("no items")?;
+        Ok(json!({ "entries": items }))
+    }
+
+    const SCHEMA: Schema = Schema {
+        file: "test.json",
+        current: 3,
+        migrations: &[nest, rename],
+    };
+
+    #[test]
+    fn test_upgrade() {
+        let (value, from) = SCHEMA.upgrade(json!({ "a": 1 })).unwrap();
+        assert_eq!((value, from), (json!({ "entries": { "a": 1 } }), 1));
+
+        let (value, from) = SCHEMA.upgrade(json!({ "version": 2, "items": [] })).unwrap();
+        assert_eq!((value, from), (json!({ "entries": [] }), 2));
+
+        let (value, from) = SCHEMA.upgrade(json!({ "version": 3, "entries": [] })).unwrap();
+        assert_eq!((value, from), (json!({ "entries": [] }), 3));
+
+        // A profile called "version" in an unversioned file is data
+        let (_, from) = SCHEMA.upgrade(json!({ "version": { "user": {} } })).unwrap();
+        assert_eq!(from, 1);
+
+        let err = SCHEMA.upgrade(json!({ "version": 4 })).unwrap_err();
+        assert!(err.contains("newer than this gamm understands (up to 3)"));
+        assert!(SCHEMA.upgrade(json!({ "version": 2 })).unwrap_err().contains("from version 2 to 3"));
+    }
+
+    #[test]
+    fn test_load_upgrades_and_backup_keeps_the_original() {
+        let dir = std::env::temp_dir().join(format!("gamm_schema_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.json");
+
+        // Loading upgrades in memory only
+        fs::write(&path, r#"{"a": 1}"#).unwrap();
+        let data: Value = SCHEMA.load(&path).unwrap().unwrap();
+        assert_eq!(data, json!({ "entries": { "a": 1 } }));
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"a": 1}"#);
+
+        let backup = SCHEMA.backup(&path).unwrap().unwrap();
+        assert_eq!(backup, Schema::backup_path(&path, 1));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), r#"{"a": 1}"#);
+
+        // A second upgrade keeps the first backup
+        fs::write(&path, r#"{"b": 2}"#).unwrap();
+        let second = SCHEMA.backup(&path).unwrap().unwrap();
+        assert_ne!(second, backup);
+        assert_eq!(fs::read_to_string(&backup).unwrap(), r#"{"a": 1}"#);
+        assert_eq!(fs::read_to_string(&second).unwrap(), r#"{"b": 2}"#);
+
+        // Current files need no backup
+        fs::write(&path, SCHEMA.to_string(&data).unwrap()).unwrap();
+        assert!(SCHEMA.backup(&path).unwrap().is_none());
+
+        // Newer files are refused and not touched
+        fs::write(&path, r#"{"version": 9}"#).unwrap();
+        assert!(SCHEMA.load::<Value>(&path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"version": 9}"#);
+
+        assert!(SCHEMA.load::<Value>(&dir.join("missing.json")).unwrap().is_none());
+        assert!(SCHEMA.backup(&dir.join("missing.json")).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
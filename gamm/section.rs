@@ -0,0 +1,102 @@
+/*
+ * section.rs
+ * Helpers for the gamm-managed block inside files we share with the user
+ * (hook scripts, ~/.gitconfig).
+ *
+ * The block is delimited by marker comments and is always rewritten as a
+ * whole, so user content outside the markers is never touched.
+ */
+
+pub const GAM_MARKER_START: &str = "# >>> gamm";
+pub const GAM_MARKER_END: &str = "# <<< gamm";
+
+/// Check whether the content contains a gamm-managed block
+pub fn contains(content: &str) -> bool {
+    content.lines().any(|l| l.trim() == GAM_MARKER_START)
+}
+
+/// Remove the gamm-managed block (including markers), keeping everything else
+pub fn strip(content: &str) -> String {
+    let mut new_content = String::new();
+    let mut in_gam_section = false;
+
+    for line in content.lines() {
+        if line.trim() == GAM_MARKER_START {
+            in_gam_section = true;
+            continue;
+        }
+        if line.trim() == GAM_MARKER_END {
+            in_gam_section = false;
+            continue;
+        }
+        if !in_gam_section {
+            new_content.push_str(line);
+            new_content.push('\n');
+        }
+    }
+
+    // Clean up extra blank lines
+    new_content.trim_end().to_string()
+}
+
+/// Wrap a body in the gamm markers
+pub fn wrap(body: &str) -> String {
+    format!("{}\n{}\n{}", GAM_MARKER_START, body.trim_end(), GAM_MARKER_END)
+}
+
+/// Replace the gamm-managed block with a new body, appending it if missing
+pub fn upsert(content: &str, body: &str) -> String {
+    let rest = strip(content);
+    if rest.is_empty() {
+        format!("{}\n", wrap(body))
+    } else {
+        format!("{}\n\n{}\n", rest, wrap(body))
+    }
+}
+
+/// Replace the body of an existing gamm-managed block where it is, for
+/// files where the block's position matters. None if there is no block.
+pub fn replace(content: &str, body: &str) -> Option<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let start = lines.iter().position(|l| l.trim() == GAM_MARKER_START)?;
+    let end = start + lines[start..].iter().position(|l| l.trim() == GAM_MARKER_END)?;
+
+    let mut out: Vec<String> = lines[..start].iter().map(|l| l.to_string()).collect();
+    out.push(wrap(body));
+    out.extend(lines[end + 1..].iter().map(|l| l.to_string()));
+    Some(format!("{}\n", out.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_keeps_user_content() {
+        let content = "[user]\n\tname = Me\n\n# >>> gamm\n[include]\n\tpath = x\n# <<< gamm\n";
+        assert_eq!(strip(content), "[user]\n\tname = Me");
+        assert!(!contains(&strip(content)));
+    }
+
+    #[test]
+    fn test_upsert_replaces_existing_block() {
+        let once = upsert("[core]\n\teditor = vim\n", "first");
+        let twice = upsert(&once, "second");
+        assert_eq!(twice, "[core]\n\teditor = vim\n\n# >>> gamm\nsecond\n# <<< gamm\n");
+    }
+
+    #[test]
+    fn test_replace_keeps_position() {
+        let content = "repos:\n# >>> gamm\nold\n# <<< gamm\n- repo: other\n";
+        assert_eq!(
+            replace(content, "new").unwrap(),
+            "repos:\n# >>> gamm\nnew\n# <<< gamm\n- repo: other\n"
+        );
+        assert!(replace("repos:\n", "new").is_none());
+    }
+
+    #[test]
+    fn test_upsert_into_empty_file() {
+        assert_eq!(upsert("", "body"), "# >>> gamm\nbody\n# <<< gamm\n");
+    }
+}
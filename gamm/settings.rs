@@ -0,0 +1,130 @@
+/*
+ * settings.rs
+ * Store-wide gamm settings that are not tied to a single profile.
+ *
+ * - scope: where profiles are applied unless a profile overrides it
+ * - remote_priority: order in which a repository's remotes are tried
+ * - restore_identity: put the global identity back after each commit
+ */
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+
+use crate::storage;
+use crate::store::Scope;
+
+/// Store-wide settings (~/.config/gamm/settings.json)
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Settings {
+    /// Default scope for applying profiles
+    #[serde(default)]
+    pub scope: Scope,
+    /// Remote names tried first, in order (e.g. `upstream` before `origin`).
+    /// Unlisted remotes follow, `origin` first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remote_priority: Vec<String>,
+    /// Snapshot the global identity before a global switch and restore it
+    /// from the post-commit hook
+    #[serde(default)]
+    pub restore_identity: bool,
+}
+
+impl Settings {
+    /// Keys accepted by `get` / `set`
+    pub const KEYS: &'static [&'static str] = &["scope", "remote-priority", "restore-identity"];
+
+    /// File name in the config dir
+    const FILE: &'static str = "settings.json";
+
+    /// Get the settings file path (~/.config/gamm/settings.json)
+    pub fn settings_path() -> Option<PathBuf> {
+        storage::config_dir().map(|p| p.join(Self::FILE))
+    }
+
+    /// Load settings from the default file, or use defaults if it doesn't exist
+    pub fn load() -> io::Result<Self> {
+        Ok(storage::read_json(Self::FILE)?.unwrap_or_default())
+    }
+
+    /// Save settings to the default file
+    pub fn save(&self) -> io::Result<()> {
+        storage::write_json(Self::FILE, self)
+    }
+
+    /// Read a setting as a display string
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "scope" => Some(self.scope.to_string()),
+            "remote-priority" => Some(self.remote_priority.join(",")),
+            "restore-identity" => Some(self.restore_identity.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Update a setting from its string form
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "scope" => self.scope = value.parse()?,
+            "remote-priority" => {
+                self.remote_priority = value
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+            }
+            "restore-identity" => {
+                self.restore_identity = value
+                    .parse()
+                    .map_err(|_| format!("invalid value '{}' (expected 'true' or 'false')", value))?;
+            }
+            other => return Err(format!("unknown setting '{}'", other)),
+        }
+        Ok(())
+    }
+
+    /// Resolve the scope a profile should be applied in
+    pub fn scope_for(&self, config: &crate::store::GitConfig) -> Scope {
+        config.scope.unwrap_or(self.scope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::GitConfig;
+
+    #[test]
+    fn test_missing_scope_defaults_to_local() {
+        let settings: Settings = serde_json::from_str("{}").unwrap();
+        assert_eq!(settings.scope, Scope::Local);
+    }
+
+    #[test]
+    fn test_get_and_set() {
+        let mut settings = Settings::default();
+        settings.set("scope", "global").unwrap();
+        assert_eq!(settings.get("scope").as_deref(), Some("global"));
+        assert!(settings.set("scope", "system").is_err());
+        assert!(settings.set("nope", "x").is_err());
+        assert!(settings.get("nope").is_none());
+
+        settings.set("remote-priority", "upstream, origin,").unwrap();
+        assert_eq!(settings.remote_priority, vec!["upstream", "origin"]);
+        assert_eq!(settings.get("remote-priority").as_deref(), Some("upstream,origin"));
+
+        settings.set("restore-identity", "true").unwrap();
+        assert!(settings.restore_identity);
+        assert!(settings.set("restore-identity", "yes").is_err());
+    }
+
+    #[test]
+    fn test_profile_scope_overrides_settings() {
+        let settings = Settings::default();
+        let mut config = GitConfig::default();
+        assert_eq!(settings.scope_for(&config), Scope::Local);
+
+        config.scope = Some(Scope::Global);
+        assert_eq!(settings.scope_for(&config), Scope::Global);
+    }
+}
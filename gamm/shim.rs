@@ -0,0 +1,294 @@
+/*
+ * shim.rs
+ * `git` shim: gamm installed as `git` ahead of the real binary on PATH.
+ *
+ * When invoked as `git`, gamm resolves the profile of the current
+ * repository and execs the real git with the identity (and SSH key)
+ * injected through GIT_AUTHOR_* / GIT_COMMITTER_* and
+ * GIT_CONFIG_COUNT/KEY/VALUE. Git sees
+ * the right identity from the start, so the first commit never has to be
+ * aborted. Any failure falls back to running the real git untouched.
+ */
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::git;
+use crate::repo::RepoStore;
+use crate::settings::Settings;
+use crate::storage;
+use crate::store::{ConfigStore, GitConfig};
+
+/// Set on the real git process so hooks calling `git` skip resolution
+const ACTIVE_ENV: &str = "GAMM_SHIM_ACTIVE";
+
+/// Overrides the real git binary lookup
+const REAL_GIT_ENV: &str = "GAMM_REAL_GIT";
+
+/// Subcommands that need the profile: those that create commits or tags,
+/// and those that talk to a remote and may need the profile's SSH key
+const PROFILE_COMMANDS: &[&str] = &[
+    "am",
+    "cherry-pick",
+    "commit",
+    "commit-tree",
+    "fetch",
+    "ls-remote",
+    "merge",
+    "notes",
+    "pull",
+    "push",
+    "rebase",
+    "revert",
+    "stash",
+    "submodule",
+    "tag",
+];
+
+/// Global git options that take a separate value argument
+const OPTIONS_WITH_VALUE: &[&str] = &[
+    "-C",
+    "-c",
+    "--git-dir",
+    "--work-tree",
+    "--namespace",
+    "--config-env",
+    "--exec-path",
+];
+
+/// Directory the shim symlink is installed into
+pub fn shim_dir() -> Option<PathBuf> {
+    storage::config_dir().map(|p| p.join("shim"))
+}
+
+/// Check whether this process was started through the `git` shim
+pub fn invoked_as_git() -> bool {
+    env::args_os()
+        .next()
+        .map(PathBuf::from)
+        .and_then(|p| p.file_name().map(|n| n == "git"))
+        .unwrap_or(false)
+}
+
+/// Find the real git binary on PATH, skipping the shim itself
+fn find_real_git() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(REAL_GIT_ENV) {
+        return Some(PathBuf::from(path));
+    }
+
+    let me = env::current_exe().ok().and_then(|p| p.canonicalize().ok());
+    let own_dir = shim_dir();
+    let path = env::var_os("PATH")?;
+
+    env::split_paths(&path)
+        .filter(|dir| Some(dir) != own_dir.as_ref())
+        .map(|dir| dir.join("git"))
+        .filter(|candidate| candidate.is_file())
+        .find(|candidate| candidate.canonicalize().ok() != me)
+}
+
+/// Parsed view of a git command line
+#[derive(Debug, Default, PartialEq)]
+struct Invocation {
+    /// Accumulated `-C` directories, in order
+    dirs: Vec<PathBuf>,
+    /// First non-option argument
+    subcommand: Option<String>,
+}
+
+fn parse_args(args: &[OsString]) -> Invocation {
+    let mut invocation = Invocation::default();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let arg = arg.to_string_lossy();
+        if !arg.starts_with('-') {
+            invocation.subcommand = Some(arg.into_owned());
+            break;
+        }
+        if arg == "-C" {
+            if let Some(dir) = iter.next() {
+                invocation.dirs.push(PathBuf::from(dir));
+            }
+        } else if OPTIONS_WITH_VALUE.contains(&arg.as_ref()) {
+            iter.next();
+        }
+    }
+
+    invocation
+}
+
+/// Resolve the profile owning the repository in the current directory
+fn resolve_profile() -> Option<(String, GitConfig)> {
+    let settings = Settings::load().ok()?;
+    let remotes = git::ordered_remotes(&settings.remote_priority);
+    let path = git::toplevel();
+    let repo_store = RepoStore::load().ok()?;
+    let owner = match repo_store.resolve_remotes(&remotes, path.as_deref(), &settings.remote_priority) {
+        Ok(found) => found?.found.commit_by.to_string(),
+        Err(conflict) => {
+            // Leave the identity alone; the pre-commit hook reports the details
+            eprintln!("gamm: not injecting an identity, {}", conflict);
+            return None;
+        }
+    };
+    let config = ConfigStore::load().ok()?.effective(&owner).ok()?;
+    Some((owner, config))
+}
+
+/// Environment variables that inject a profile into a git process
+fn identity_env(config: &GitConfig, existing_count: usize) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+
+    if !config.user.name.is_empty() {
+        env.push(("GIT_AUTHOR_NAME".into(), config.user.name.clone()));
+        env.push(("GIT_COMMITTER_NAME".into(), config.user.name.clone()));
+    }
+    if !config.user.email.is_empty() {
+        env.push(("GIT_AUTHOR_EMAIL".into(), config.user.email.clone()));
+        env.push(("GIT_COMMITTER_EMAIL".into(), config.user.email.clone()));
+    }
+
+    let entries = config.entries();
+    for (i, (key, value)) in entries.iter().enumerate() {
+        env.push((format!("GIT_CONFIG_KEY_{}", existing_count + i), key.clone()));
+        env.push((format!("GIT_CONFIG_VALUE_{}", existing_count + i), value.clone()));
+    }
+    env.push(("GIT_CONFIG_COUNT".into(), (existing_count + entries.len()).to_string()));
+
+    env
+}
+
+/// Run as `git`: inject the profile if we can resolve one, then exec the real git
+pub fn run() -> ! {
+    let args: Vec<OsString> = env::args_os().skip(1).collect();
+
+    let Some(real_git) = find_real_git() else {
+        eprintln!("gamm: could not find the real git binary on PATH");
+        std::process::exit(127);
+    };
+
+    let mut cmd = Command::new(&real_git);
+    cmd.args(&args).env(ACTIVE_ENV, "1");
+
+    let invocation = parse_args(&args);
+    let needs_profile = invocation
+        .subcommand
+        .as_deref()
+        .is_some_and(|s| PROFILE_COMMANDS.contains(&s));
+
+    if env::var_os(ACTIVE_ENV).is_none() && needs_profile {
+        git::set_binary(real_git.clone());
+        if let Some((_, config)) = in_dir(&invocation.dirs, resolve_profile) {
+            let existing_count = env::var("GIT_CONFIG_COUNT")
+                .ok()
+                .and_then(|c| c.parse().ok())
+                .unwrap_or(0);
+            cmd.envs(identity_env(&config, existing_count));
+        }
+    }
+
+    let err = cmd.exec();
+    eprintln!("gamm: failed to run {}: {}", real_git.display(), err);
+    std::process::exit(127);
+}
+
+/// Run `f` from the directory selected by `-C` options, then restore the cwd
+fn in_dir<T>(dirs: &[PathBuf], f: impl FnOnce() -> Option<T>) -> Option<T> {
+    if dirs.is_empty() {
+        return f();
+    }
+
+    let original = env::current_dir().ok()?;
+    let target = dirs.iter().fold(original.clone(), |acc, d| acc.join(d));
+    env::set_current_dir(&target).ok()?;
+    let result = f();
+    let _ = env::set_current_dir(&original);
+    result
+}
+
+/// Install the shim as `<shim dir>/git`, returning the directory to put on PATH
+pub fn install() -> io::Result<PathBuf> {
+    let dir = shim_dir().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "Could not find config directory")
+    })?;
+    let link = dir.join("git");
+    let exe = env::current_exe()?;
+
+    fs::create_dir_all(&dir)?;
+    if link.symlink_metadata().is_ok() {
+        fs::remove_file(&link)?;
+    }
+    symlink(&exe, &link)?;
+
+    Ok(dir)
+}
+
+/// Remove the shim, returning the removed link if there was one
+pub fn uninstall() -> io::Result<Option<PathBuf>> {
+    let Some(dir) = shim_dir() else {
+        return Ok(None);
+    };
+    let link = dir.join("git");
+
+    if link.symlink_metadata().is_err() {
+        return Ok(None);
+    }
+
+    fs::remove_file(&link)?;
+    if is_empty_dir(&dir) {
+        fs::remove_dir(&dir)?;
+    }
+
+    Ok(Some(link))
+}
+
+fn is_empty_dir(dir: &Path) -> bool {
+    dir.read_dir().is_ok_and(|mut d| d.next().is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{CommitConfig, UserConfig};
+
+    fn args(list: &[&str]) -> Vec<OsString> {
+        list.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args_finds_subcommand_after_global_options() {
+        let invocation = parse_args(&args(&["-C", "sub", "-c", "a.b=c", "--no-pager", "commit", "-m", "x"]));
+        assert_eq!(invocation.subcommand.as_deref(), Some("commit"));
+        assert_eq!(invocation.dirs, vec![PathBuf::from("sub")]);
+
+        let invocation = parse_args(&args(&["--version"]));
+        assert!(invocation.subcommand.is_none());
+    }
+
+    #[test]
+    fn test_identity_env_appends_to_existing_config_count() {
+        let config = GitConfig {
+            user: UserConfig {
+                name: "Jane".into(),
+                email: "jane@work.com".into(),
+                signoff: None,
+            },
+            commit: CommitConfig { gpgsign: Some(false) },
+            ..Default::default()
+        };
+
+        let env = identity_env(&config, 2);
+        assert!(env.contains(&("GIT_AUTHOR_EMAIL".into(), "jane@work.com".into())));
+        assert!(env.contains(&("GIT_COMMITTER_NAME".into(), "Jane".into())));
+        assert!(env.contains(&("GIT_CONFIG_KEY_2".into(), "user.name".into())));
+        assert!(env.contains(&("GIT_CONFIG_VALUE_4".into(), "false".into())));
+        assert!(env.contains(&("GIT_CONFIG_COUNT".into(), "5".into())));
+    }
+}
@@ -0,0 +1,204 @@
+/*
+ * status.rs
+ * Compare a profile with the configuration git actually uses.
+ *
+ * Every key the profile writes is looked up in all scopes together with the
+ * scope its value comes from (`git config --show-scope`). Keys the profile
+ * sets to `true` or `false` are read as booleans, so `yes`, `on` or `1`
+ * written by hand count as the same value. Keys and URL
+ * rewrites gamm wrote for another profile that this one leaves unset must
+ * not be set in the scope the profile is applied to, because switching
+ * would remove them there.
+ */
+
+use crate::git;
+use crate::managed::{self, Managed};
+use crate::store::{GitConfig, Scope};
+
+/// How a key compares with the profile
+#[derive(Debug, PartialEq)]
+pub enum State {
+    /// Git uses the profile's value(s)
+    Matches,
+    /// Git uses another value, or none
+    Differs,
+    /// The key is set although the profile would remove it
+    Unexpected,
+}
+
+/// One key of the comparison
+#[derive(Debug, PartialEq)]
+pub struct KeyStatus {
+    pub key: String,
+    /// Values the profile sets (empty for keys it removes)
+    pub expected: Vec<String>,
+    /// Values git has, as (scope, value), in the order git reads them
+    pub actual: Vec<(String, String)>,
+    pub state: State,
+}
+
+impl KeyStatus {
+    pub fn drifted(&self) -> bool {
+        self.state != State::Matches
+    }
+}
+
+/// Compare expected entries with what git has.
+///
+/// `origins` returns every value of a key across all scopes with its scope;
+/// `in_scope` returns the values of a key in the profile's scope only.
+fn compare(
+    entries: &[(String, String)],
+    stale: &[Managed],
+    origins: impl Fn(&str) -> Vec<(String, String)>,
+    in_scope: impl Fn(&str) -> Vec<String>,
+) -> Vec<KeyStatus> {
+    let mut statuses: Vec<KeyStatus> = Vec::new();
+
+    // Group repeated keys, keeping the order of the entries
+    for (key, value) in entries {
+        match statuses.iter_mut().find(|s| s.key == *key) {
+            Some(status) => status.expected.push(value.clone()),
+            None => statuses.push(KeyStatus {
+                key: key.clone(),
+                expected: vec![value.clone()],
+                actual: Vec::new(),
+                state: State::Matches,
+            }),
+        }
+    }
+
+    for status in &mut statuses {
+        status.actual = origins(&status.key);
+        let values: Vec<&String> = status.actual.iter().map(|(_, v)| v).collect();
+
+        // Multi-valued keys only need to contain the profile's values; for
+        // the others git uses the last value it reads
+        let matches = if status.expected.len() > 1 || GitConfig::is_multi_valued(&status.key) {
+            status.expected.iter().all(|v| values.contains(&v))
+        } else {
+            values.last() == status.expected.first().as_ref()
+        };
+        if !matches {
+            status.state = State::Differs;
+        }
+    }
+
+    for entry in stale {
+        let (key, value) = match entry {
+            Managed::Key(key) => (key, None),
+            Managed::Value(key, value) => (key, Some(value)),
+        };
+        let values = in_scope(key);
+        let set = match value {
+            Some(value) => values.contains(value),
+            None => !values.is_empty(),
+        };
+        if set {
+            statuses.push(KeyStatus {
+                key: key.clone(),
+                expected: Vec::new(),
+                actual: origins(key).into_iter().filter(|(_, v)| value.is_none_or(|value| v == value)).collect(),
+                state: State::Unexpected,
+            });
+        }
+    }
+
+    statuses
+}
+
+/// Keys the entries set to a boolean, which git reads in any spelling
+fn bool_keys(entries: &[(String, String)]) -> Vec<&str> {
+    entries
+        .iter()
+        .filter(|(key, value)| {
+            (value == "true" || value == "false") && entries.iter().filter(|(k, _)| k == key).count() == 1
+        })
+        .map(|(key, _)| key.as_str())
+        .collect()
+}
+
+/// Compare the effective profile `config`, applied in `scope`, with the git
+/// configuration of the current directory
+pub fn check(config: &GitConfig, scope: Scope) -> Vec<KeyStatus> {
+    let entries = config.entries();
+    let bools = bool_keys(&entries);
+
+    compare(
+        &entries,
+        &managed::stale(&managed::read(scope), &entries),
+        |key| {
+            if bools.contains(&key) {
+                git::config_origins_bool(key)
+            } else {
+                git::config_origins(key)
+            }
+        },
+        |key| git::config_get_all(scope, key),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let entries = pairs(&[
+            ("user.name", "Jane"),
+            ("user.email", "jane@acme.com"),
+            ("commit.gpgsign", "true"),
+            ("url.git@github.com:.insteadOf", "https://github.com/"),
+        ]);
+        let origins = |key: &str| match key {
+            "user.name" => pairs(&[("global", "Me"), ("local", "Jane")]),
+            "user.email" => pairs(&[("local", "jane@acme.com"), ("worktree", "me@home.dev")]),
+            "url.git@github.com:.insteadOf" => pairs(&[("global", "gh:"), ("local", "https://github.com/")]),
+            "user.signingkey" => pairs(&[("local", "ABC")]),
+            _ => vec![],
+        };
+        let in_scope = |key: &str| match key {
+            "user.signingkey" => vec!["ABC".to_string()],
+            "url.git@gitlab.com:.insteadOf" => vec!["https://gitlab.com/".to_string()],
+            _ => vec![],
+        };
+
+        let statuses = compare(
+            &entries,
+            &[
+                Managed::Key("gpg.format".into()),
+                Managed::Key("user.signingkey".into()),
+                Managed::Value("url.git@gitlab.com:.insteadOf".into(), "https://gitlab.com/".into()),
+            ],
+            origins,
+            in_scope,
+        );
+        let state = |key: &str| &statuses.iter().find(|s| s.key == key).unwrap().state;
+
+        assert_eq!(*state("user.name"), State::Matches);
+        assert_eq!(*state("user.email"), State::Differs);
+        assert_eq!(*state("commit.gpgsign"), State::Differs);
+        assert_eq!(*state("url.git@github.com:.insteadOf"), State::Matches);
+        assert_eq!(*state("user.signingkey"), State::Unexpected);
+        assert_eq!(*state("url.git@gitlab.com:.insteadOf"), State::Unexpected);
+        assert!(!statuses.iter().any(|s| s.key == "gpg.format"));
+        assert_eq!(statuses.iter().filter(|s| s.drifted()).count(), 4);
+    }
+
+    #[test]
+    fn test_bool_keys() {
+        let entries = pairs(&[
+            ("user.name", "Jane"),
+            ("commit.gpgsign", "false"),
+            ("pull.rebase", "true"),
+            ("core.autocrlf", "input"),
+            ("url.a:.insteadOf", "true"),
+            ("url.a:.insteadOf", "b:"),
+        ]);
+        assert_eq!(bool_keys(&entries), vec!["commit.gpgsign", "pull.rebase"]);
+    }
+}
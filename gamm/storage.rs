@@ -0,0 +1,408 @@
+/*
+ * storage.rs
+ * Where gamm keeps its files, and safe writes and cross-process locking for
+ * them.
+ *
+ * - `config_dir` resolves the config dir once for every store: the
+ *   --config-dir flag, then $GAMM_CONFIG_DIR, then $XDG_CONFIG_HOME/gamm,
+ *   then the platform's config dir (~/.config/gamm on Linux). Hooks only see
+ *   the environment, so hooks installed with --config-dir export it as
+ *   GAMM_CONFIG_DIR; the shim and hook managers need GAMM_CONFIG_DIR set.
+ * - `write_atomic` writes a temp file next to the target, fsyncs it and
+ *   renames it over the target, so readers see the old or the new file and
+ *   never a truncated one. A symlinked target is followed, so the link
+ *   stays, and the new file keeps the old one's permissions.
+ * - `write_all_atomic` does the same for several files that must change
+ *   together (config.json and repos.json). The temp files are listed in
+ *   <config dir>/gamm.journal before the first rename; if gamm dies between
+ *   renames, `recover` finishes them on the next load, so the files are
+ *   never left half old and half new.
+ * - `lock` takes an advisory lock on <config dir>/gamm.lock. Commands that
+ *   load, modify and save the stores hold it from load to save, so two
+ *   commits at once (or a commit during `gamm profile delete`) can't lose
+ *   each other's updates. Loading never writes (besides `recover`, which
+ *   waits for the lock), so commands that only read don't take it. Taking
+ *   it again in the same process is a no-op. The OS drops the lock when the
+ *   process exits, so a crashed gamm never leaves it behind.
+ */
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::ffi::OsString;
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for another gamm process before giving up
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often to retry while waiting for the lock
+const LOCK_RETRY: Duration = Duration::from_millis(50);
+
+/// Commits in progress, as (temp file, target) pairs
+const JOURNAL: &str = "gamm.journal";
+
+/// Whether this process holds the config dir lock
+static LOCK_HELD: AtomicBool = AtomicBool::new(false);
+
+/// Config dir given with --config-dir
+static CONFIG_DIR_FLAG: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `dir` as the config dir for the rest of the process (--config-dir)
+pub fn set_config_dir(dir: PathBuf) {
+    let _ = CONFIG_DIR_FLAG.set(dir);
+}
+
+/// The config dir every store reads and writes
+pub fn config_dir() -> Option<PathBuf> {
+    resolve_config_dir(
+        CONFIG_DIR_FLAG.get().cloned(),
+        std::env::var_os("GAMM_CONFIG_DIR"),
+        std::env::var_os("XDG_CONFIG_HOME"),
+    )
+}
+
+/// The config dir given with --config-dir, if any, spelled the same
+/// however it was given (for what gamm installs)
+pub fn config_dir_flag() -> Option<PathBuf> {
+    CONFIG_DIR_FLAG.get().and(config_dir()).map(|dir| fs::canonicalize(&dir).unwrap_or(dir))
+}
+
+/// The config dir, or an error when there is none
+pub fn require_config_dir() -> io::Result<PathBuf> {
+    config_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find config directory"))
+}
+
+fn resolve_config_dir(flag: Option<PathBuf>, gamm: Option<OsString>, xdg: Option<OsString>) -> Option<PathBuf> {
+    let set = |var: Option<OsString>| var.filter(|v| !v.is_empty()).map(PathBuf::from);
+
+    flag.or_else(|| set(gamm))
+        // The XDG spec says to ignore relative paths
+        .or_else(|| set(xdg).filter(|p| p.is_absolute()).map(|p| p.join("gamm")))
+        .or_else(|| dirs::config_dir().map(|p| p.join("gamm")))
+        // Commands may change directory, so pin relative paths now
+        .map(|p| std::path::absolute(&p).unwrap_or(p))
+}
+
+/// Read a JSON file in the config dir. Returns None when it doesn't exist.
+pub fn read_json<T: DeserializeOwned>(file: &str) -> io::Result<Option<T>> {
+    let path = require_config_dir()?.join(file);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(&path)?;
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write a JSON file in the config dir atomically, creating the dir
+pub fn write_json<T: Serialize>(file: &str, data: &T) -> io::Result<()> {
+    let dir = require_config_dir()?;
+    fs::create_dir_all(&dir)?;
+
+    let contents = serde_json::to_string_pretty(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_atomic(&dir.join(file), contents.as_bytes())
+}
+
+/// Directory holding `path`
+fn parent_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// The file `path` points at, following symlinks, so replacing it keeps
+/// the links (`path` itself when it doesn't exist yet)
+fn resolve(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Write `contents` to a synced temp file next to `path`, with the
+/// permissions of `path` if it exists
+fn write_temp(path: &Path, contents: &[u8]) -> io::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let temp = parent_dir(path).join(format!(".{}.{}.tmp", name.to_string_lossy(), std::process::id()));
+
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(contents)?;
+        file.sync_all()
+    })();
+
+    match result {
+        Ok(()) => Ok(temp),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Persist renames and removals in `dir`
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Replace a file's contents atomically
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = resolve(path);
+    let temp = write_temp(&path, contents)?;
+    if let Err(e) = fs::rename(&temp, &path) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    sync_dir(parent_dir(&path))
+}
+
+/// Replace the contents of several files in `dir` as one change: after a
+/// crash, `recover` leaves either all of them new or all of them old
+pub fn write_all_atomic(dir: &Path, files: &[(PathBuf, Vec<u8>)]) -> io::Result<()> {
+    let mut staged: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let discard = |staged: &[(PathBuf, PathBuf)]| {
+        for (temp, _) in staged {
+            let _ = fs::remove_file(temp);
+        }
+    };
+
+    for (path, contents) in files {
+        let path = resolve(path);
+        match write_temp(&path, contents) {
+            Ok(temp) => staged.push((temp, path)),
+            Err(e) => {
+                discard(&staged);
+                return Err(e);
+            }
+        }
+    }
+
+    // Once the journal is on disk the change counts as made
+    let journal = dir.join(JOURNAL);
+    let entries = serde_json::to_vec(&staged).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if let Err(e) = write_atomic(&journal, &entries) {
+        discard(&staged);
+        return Err(e);
+    }
+
+    apply(dir, &staged)
+}
+
+/// Rename staged files over their targets and drop the journal
+fn apply(dir: &Path, staged: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    for (temp, path) in staged {
+        // Already renamed by an earlier, interrupted attempt
+        if temp.exists() {
+            fs::rename(temp, path)?;
+        }
+    }
+    // Symlinked targets may live outside `dir`
+    for (_, path) in staged {
+        sync_dir(parent_dir(path))?;
+    }
+    fs::remove_file(dir.join(JOURNAL))?;
+    sync_dir(dir)
+}
+
+/// Whether a `write_all_atomic` in `dir` was interrupted, so the next load
+/// will finish it
+pub fn pending(dir: &Path) -> bool {
+    dir.join(JOURNAL).exists()
+}
+
+/// Finish a `write_all_atomic` that was interrupted in `dir`. Returns true
+/// if there was one.
+pub fn recover(dir: &Path) -> io::Result<bool> {
+    let journal = dir.join(JOURNAL);
+    if !journal.exists() {
+        return Ok(false);
+    }
+
+    // The writer may still be running; wait for it unless it is us
+    let _lock = if LOCK_HELD.load(Ordering::SeqCst) {
+        None
+    } else {
+        Some(lock_file(&dir.join("gamm.lock"), LOCK_TIMEOUT)?)
+    };
+
+    let entries = match fs::read(&journal) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let staged: Vec<(PathBuf, PathBuf)> = serde_json::from_slice(&entries)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", journal.display(), e)))?;
+    apply(dir, &staged)?;
+    Ok(true)
+}
+
+/// Held lock on the config dir; released when the guard that took it is
+/// dropped
+#[derive(Debug)]
+pub struct Lock {
+    /// None for a guard taken while this process already held the lock
+    file: Option<File>,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            LOCK_HELD.store(false, Ordering::SeqCst);
+            let _ = file.unlock();
+        }
+    }
+}
+
+/// Path of the lock file (~/.config/gamm/gamm.lock)
+pub fn lock_path() -> Option<PathBuf> {
+    config_dir().map(|p| p.join("gamm.lock"))
+}
+
+/// Take the lock on the config dir, waiting for other gamm processes
+pub fn lock() -> io::Result<Lock> {
+    if LOCK_HELD.load(Ordering::SeqCst) {
+        return Ok(Lock { file: None });
+    }
+    let lock = lock_file(&require_config_dir()?.join("gamm.lock"), LOCK_TIMEOUT)?;
+    LOCK_HELD.store(true, Ordering::SeqCst);
+    Ok(lock)
+}
+
+fn lock_file(path: &Path, timeout: Duration) -> io::Result<Lock> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = File::options().create(true).truncate(false).write(true).open(path)?;
+
+    let started = Instant::now();
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(Lock { file: Some(file) }),
+            Err(TryLockError::WouldBlock) if started.elapsed() < timeout => thread::sleep(LOCK_RETRY),
+            Err(TryLockError::WouldBlock) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!(
+                        "another gamm process is using {} (gave up after {}s); try again once it has finished",
+                        path.parent().unwrap_or(path).display(),
+                        timeout.as_secs()
+                    ),
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_dir_exists() {
+        let dir = config_dir();
+        assert!(dir.is_some());
+        if std::env::var_os("GAMM_CONFIG_DIR").is_none() {
+            assert!(dir.unwrap().ends_with("gamm"));
+        }
+    }
+
+    #[test]
+    fn test_resolve_config_dir() {
+        let flag = Some(PathBuf::from("/srv/gamm-flag"));
+        let gamm = Some(OsString::from("/srv/gamm-env"));
+        let xdg = Some(OsString::from("/srv/xdg"));
+
+        assert_eq!(resolve_config_dir(flag, gamm.clone(), xdg.clone()), Some(PathBuf::from("/srv/gamm-flag")));
+        assert_eq!(resolve_config_dir(None, gamm, xdg.clone()), Some(PathBuf::from("/srv/gamm-env")));
+        assert_eq!(resolve_config_dir(None, Some(OsString::new()), xdg), Some(PathBuf::from("/srv/xdg/gamm")));
+
+        // A relative XDG_CONFIG_HOME is ignored; relative gamm paths are pinned
+        let fallback = resolve_config_dir(None, None, Some(OsString::from("xdg"))).unwrap();
+        assert!(fallback.is_absolute() && fallback.ends_with("gamm") && !fallback.ends_with("xdg/gamm"));
+        let relative = resolve_config_dir(None, Some(OsString::from("roles/work")), None).unwrap();
+        assert_eq!(relative, std::env::current_dir().unwrap().join("roles/work"));
+    }
+
+    #[test]
+    fn test_write_atomic_and_lock() {
+        let dir = std::env::temp_dir().join(format!("gamm_storage_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let file = dir.join("config.json");
+        write_atomic(&file, b"{\"a\": 1}").unwrap();
+        write_atomic(&file, b"{}").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "{}");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1, "no temp file left behind");
+
+        // A symlinked file stays a symlink and keeps its mode
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let target = dir.join("real.json");
+            let link = dir.join("link.json");
+            fs::write(&target, "{}").unwrap();
+            fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+            std::os::unix::fs::symlink(&target, &link).unwrap();
+
+            write_atomic(&link, b"{\"b\": 2}").unwrap();
+            assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+            assert_eq!(fs::read_to_string(&target).unwrap(), "{\"b\": 2}");
+            assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o600);
+
+            fs::remove_file(&link).unwrap();
+            fs::remove_file(&target).unwrap();
+        }
+
+        let lock_path = dir.join("gamm.lock");
+        let held = lock_file(&lock_path, Duration::ZERO).unwrap();
+        let err = lock_file(&lock_path, Duration::from_millis(120)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(err.to_string().contains("another gamm process"));
+
+        drop(held);
+        assert!(lock_file(&lock_path, Duration::ZERO).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_all_atomic_and_recover() {
+        let dir = std::env::temp_dir().join(format!("gamm_storage_journal_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (config, repos) = (dir.join("config.json"), dir.join("repos.json"));
+
+        write_all_atomic(&dir, &[(config.clone(), b"c1".to_vec()), (repos.clone(), b"r1".to_vec())]).unwrap();
+        assert_eq!(fs::read_to_string(&config).unwrap(), "c1");
+        assert_eq!(fs::read_to_string(&repos).unwrap(), "r1");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2, "no journal or temp file left behind");
+
+        // Interrupted after the first of two renames
+        let staged = vec![
+            (write_temp(&config, b"c2").unwrap(), config.clone()),
+            (write_temp(&repos, b"r2").unwrap(), repos.clone()),
+        ];
+        fs::write(dir.join(JOURNAL), serde_json::to_vec(&staged).unwrap()).unwrap();
+        fs::rename(&staged[0].0, &config).unwrap();
+        assert_eq!(fs::read_to_string(&repos).unwrap(), "r1");
+
+        assert!(pending(&dir));
+        assert!(recover(&dir).unwrap());
+        assert_eq!(fs::read_to_string(&config).unwrap(), "c2");
+        assert_eq!(fs::read_to_string(&repos).unwrap(), "r2");
+        assert!(!dir.join(JOURNAL).exists());
+        assert!(!recover(&dir).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,54 +1,448 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+
+use crate::document::{self, Format};
+use crate::schema::Schema;
+use crate::storage;
 
 /// User configuration section
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UserConfig {
+    /// Empty means unset (inherited when the profile extends another)
+    #[serde(default)]
     pub email: String,
+    #[serde(default)]
     pub name: String,
+    /// `Signed-off-by` trailer value (a template, see TrailerConfig)
     pub signoff: Option<String>,
 }
 
-/// URL rewrite rule
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A commit message trailer added for a profile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrailerConfig {
+    pub key: String,
+    /// Value template; `{name}`, `{email}`, `{profile}` and `{change_id}`
+    /// are replaced when the trailer is added
+    pub value: String,
+}
+
+impl TrailerConfig {
+    /// Parse `Key: value`
+    pub fn parse(trailer: &str) -> Result<Self, String> {
+        let (key, value) = trailer
+            .split_once(':')
+            .ok_or_else(|| format!("invalid trailer '{}' (expected 'Key: value')", trailer))?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || key.contains(char::is_whitespace) || value.is_empty() {
+            return Err(format!("invalid trailer '{}' (expected 'Key: value')", trailer));
+        }
+        Ok(Self {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    /// Whether the value is generated per commit. An existing trailer with
+    /// this key is then kept as is instead of adding a second one.
+    pub fn is_generated(&self) -> bool {
+        self.value.contains("{change_id}")
+    }
+}
+
+/// URL rewrite rule (`url.<pattern>.insteadOf = <instead_of>`): remote URLs
+/// starting with `instead_of` are rewritten to start with `pattern`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UrlConfig {
     pub pattern: String,
     pub instead_of: String,
 }
 
+impl UrlConfig {
+    /// Git config key holding this rewrite
+    pub fn key(&self) -> String {
+        format!("url.{}.insteadOf", self.pattern)
+    }
+}
+
 /// Commit configuration section
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CommitConfig {
-    pub gpgsign: bool,
+    /// Unset inherits from the base profile (and means off without one)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpgsign: Option<bool>,
+}
+
+/// Tag configuration section
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TagConfig {
+    /// Unset inherits from the base profile (and means off without one)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpgsign: Option<bool>,
+}
+
+/// Signature format (`gpg.format`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GpgFormat {
+    #[default]
+    Openpgp,
+    Ssh,
+    X509,
+}
+
+impl GpgFormat {
+    pub const ALL: [GpgFormat; 3] = [GpgFormat::Openpgp, GpgFormat::Ssh, GpgFormat::X509];
+}
+
+impl std::fmt::Display for GpgFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GpgFormat::Openpgp => write!(f, "openpgp"),
+            GpgFormat::Ssh => write!(f, "ssh"),
+            GpgFormat::X509 => write!(f, "x509"),
+        }
+    }
+}
+
+impl std::str::FromStr for GpgFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openpgp" => Ok(GpgFormat::Openpgp),
+            "ssh" => Ok(GpgFormat::Ssh),
+            "x509" => Ok(GpgFormat::X509),
+            other => Err(format!("invalid signing format '{}' (expected 'openpgp', 'ssh' or 'x509')", other)),
+        }
+    }
+}
+
+/// Signing configuration section
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SigningConfig {
+    /// `gpg.format`; git's default (openpgp) when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<GpgFormat>,
+    /// `user.signingkey`: key id, X.509 identity or SSH key path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Signing program for openpgp (`gpg.program`) or x509 (`gpg.x509.program`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+    /// `gpg.ssh.program`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_program: Option<String>,
+}
+
+impl SigningConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Where gamm writes a profile's git config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// The repository's own `.git/config` (`git config --local`)
+    #[default]
+    Local,
+    /// The user's `~/.gitconfig` (`git config --global`)
+    Global,
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::Local => write!(f, "local"),
+            Scope::Global => write!(f, "global"),
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Scope::Local),
+            "global" => Ok(Scope::Global),
+            other => Err(format!("invalid scope '{}' (expected 'local' or 'global')", other)),
+        }
+    }
 }
 
 /// A complete git configuration profile
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GitConfig {
+    #[serde(default)]
     pub user: UserConfig,
+    #[serde(default)]
     pub urls: Vec<UrlConfig>,
+    #[serde(default)]
     pub commit: CommitConfig,
+    #[serde(default, skip_serializing_if = "SigningConfig::is_empty")]
+    pub signing: SigningConfig,
+    #[serde(default)]
+    pub tag: TagConfig,
+    /// Trailers added to every commit message, after the sign-off
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailers: Vec<TrailerConfig>,
+    /// Private key used for SSH remotes (`core.sshCommand`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key: Option<String>,
+    /// Any other git config keys, each with one or more values
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "one_or_many"
+    )]
+    pub extra: BTreeMap<String, Vec<String>>,
+    /// Base profile this one inherits unset fields from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Overrides the store-wide scope from Settings for this profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scope>,
+}
+
+impl GitConfig {
+    /// Optional keys gamm manages through dedicated profile fields. When the
+    /// next profile doesn't set one gamm wrote, it is unset on switch (see
+    /// managed.rs).
+    pub const OPTIONAL_KEYS: &'static [&'static str] = &[
+        "user.signingkey",
+        "gpg.format",
+        "gpg.program",
+        "gpg.x509.program",
+        "gpg.ssh.program",
+        "tag.gpgsign",
+        "core.sshCommand",
+    ];
+
+    /// Git config entries this profile sets, in the order they are applied.
+    /// A key may appear more than once for multi-valued settings.
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+
+        if !self.user.name.is_empty() {
+            entries.push(("user.name".into(), self.user.name.clone()));
+        }
+        if !self.user.email.is_empty() {
+            entries.push(("user.email".into(), self.user.email.clone()));
+        }
+        entries.push(("commit.gpgsign".into(), self.commit.gpgsign.unwrap_or(false).to_string()));
+
+        if let Some(key) = &self.signing.key {
+            entries.push(("user.signingkey".into(), key.clone()));
+        }
+        if let Some(format) = self.signing.format {
+            entries.push(("gpg.format".into(), format.to_string()));
+        }
+        if let Some(program) = &self.signing.program {
+            let key = match self.signing.format {
+                Some(GpgFormat::X509) => "gpg.x509.program",
+                _ => "gpg.program",
+            };
+            entries.push((key.into(), program.clone()));
+        }
+        if let Some(program) = &self.signing.ssh_program {
+            entries.push(("gpg.ssh.program".into(), program.clone()));
+        }
+        if self.tag.gpgsign == Some(true) {
+            entries.push(("tag.gpgsign".into(), "true".into()));
+        }
+        if let Some(command) = self.ssh_command() {
+            entries.push(("core.sshCommand".into(), command));
+        }
+
+        for (key, values) in &self.extra {
+            for value in values {
+                entries.push((key.clone(), value.clone()));
+            }
+        }
+
+        for url in &self.urls {
+            entries.push((url.key(), url.instead_of.clone()));
+        }
+
+        entries
+    }
+
+    /// This profile laid over `base`: every field this profile sets wins,
+    /// everything else comes from `base`. Lists are replaced as a whole,
+    /// and extra keys are merged key by key.
+    pub fn merged_over(&self, base: &GitConfig) -> GitConfig {
+        let text = |own: &String, base: &String| if own.is_empty() { base.clone() } else { own.clone() };
+        fn list<T: Clone>(own: &[T], base: &[T]) -> Vec<T> {
+            if own.is_empty() { base.to_vec() } else { own.to_vec() }
+        }
+
+        let mut extra = base.extra.clone();
+        extra.extend(self.extra.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+        GitConfig {
+            user: UserConfig {
+                email: text(&self.user.email, &base.user.email),
+                name: text(&self.user.name, &base.user.name),
+                signoff: self.user.signoff.clone().or_else(|| base.user.signoff.clone()),
+            },
+            urls: list(&self.urls, &base.urls),
+            commit: CommitConfig {
+                gpgsign: self.commit.gpgsign.or(base.commit.gpgsign),
+            },
+            signing: SigningConfig {
+                format: self.signing.format.or(base.signing.format),
+                key: self.signing.key.clone().or_else(|| base.signing.key.clone()),
+                program: self.signing.program.clone().or_else(|| base.signing.program.clone()),
+                ssh_program: self.signing.ssh_program.clone().or_else(|| base.signing.ssh_program.clone()),
+            },
+            tag: TagConfig {
+                gpgsign: self.tag.gpgsign.or(base.tag.gpgsign),
+            },
+            trailers: list(&self.trailers, &base.trailers),
+            ssh_key: self.ssh_key.clone().or_else(|| base.ssh_key.clone()),
+            extra,
+            extends: base.extends.clone(),
+            scope: self.scope.or(base.scope),
+        }
+    }
+
+    /// Check that a key can be stored as an extra key: it must be a full
+    /// `section.name` key that no dedicated profile field manages
+    pub fn validate_extra_key(key: &str) -> Result<(), String> {
+        let lower = key.to_lowercase();
+        let (section, name) = lower.rsplit_once('.').unwrap_or(("", ""));
+        if section.is_empty() || name.is_empty() || key.contains(char::is_whitespace) {
+            return Err(format!("invalid key '{}' (expected 'section.name')", key));
+        }
+
+        let managed = ["user.name", "user.email", "commit.gpgsign"]
+            .iter()
+            .chain(Self::OPTIONAL_KEYS)
+            .any(|k| k.to_lowercase() == lower);
+        if managed || lower.starts_with("url.") || lower.starts_with("gamm.") {
+            return Err(format!("'{}' is managed by gamm; use the profile's own fields for it", key));
+        }
+        Ok(())
+    }
+
+    /// Whether a key can hold several values, so applying a profile adds
+    /// its value instead of replacing the existing ones
+    pub fn is_multi_valued(key: &str) -> bool {
+        key.starts_with("url.")
+    }
+
+    /// Trailer templates this profile adds to commit messages, sign-off first
+    pub fn trailer_templates(&self) -> Vec<TrailerConfig> {
+        let mut trailers = Vec::new();
+        if let Some(signoff) = self.user.signoff.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+            trailers.push(TrailerConfig {
+                key: "Signed-off-by".into(),
+                value: signoff.to_string(),
+            });
+        }
+        trailers.extend(self.trailers.iter().cloned());
+        trailers
+    }
+
+    /// Expand the placeholders of a trailer value template
+    pub fn expand(&self, template: &str, profile: &str, change_id: &str) -> String {
+        template
+            .replace("{name}", &self.user.name)
+            .replace("{email}", &self.user.email)
+            .replace("{profile}", profile)
+            .replace("{change_id}", change_id)
+    }
+
+    /// `core.sshCommand` that makes ssh use only this profile's key
+    pub fn ssh_command(&self) -> Option<String> {
+        let key = self.ssh_key.as_deref().map(str::trim).filter(|k| !k.is_empty())?;
+        Some(format!("ssh -i {} -o IdentitiesOnly=yes", shell_quote(key)))
+    }
+}
+
+/// Accept `"key": "value"` as well as `"key": ["a", "b"]` for extra keys
+fn one_or_many<'de, D>(deserializer: D) -> Result<BTreeMap<String, Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Values {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let map = BTreeMap::<String, Values>::deserialize(deserializer)?;
+    Ok(map
+        .into_iter()
+        .map(|(key, values)| match values {
+            Values::One(value) => (key, vec![value]),
+            Values::Many(values) => (key, values),
+        })
+        .collect())
+}
+
+/// Quote a path for the shell git runs `core.sshCommand` with, keeping a
+/// leading `~/` outside the quotes so it is still expanded
+pub fn shell_quote(path: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "/._-~+@:,=".contains(c);
+    if path.chars().all(safe) {
+        return path.to_string();
+    }
+
+    let (prefix, rest) = match path.strip_prefix("~/") {
+        Some(rest) => ("~/", rest),
+        None => ("", path),
+    };
+    format!("{}'{}'", prefix, rest.replace('\'', "'\\''"))
+}
+
+/// Expand a leading `~/` in a path from a profile
+pub fn expand_home(path: &str, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
 }
 
 /// Store for managing multiple git config profiles
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ConfigStore {
-    #[serde(flatten)]
+    #[serde(rename = "profiles", default)]
     configs: HashMap<String, GitConfig>,
 }
 
-impl ConfigStore {
-    /// Get the config directory path (~/.config/gam or platform equivalent)
-    pub fn config_dir() -> Option<PathBuf> {
-        dirs::config_dir().map(|p| p.join("gamm"))
+/// Layout history of config.json
+const SCHEMA: Schema = Schema {
+    file: "config.json",
+    current: 2,
+    migrations: &[nest_profiles],
+};
+
+/// Version 1 was the profile map itself; version 2 moves it under "profiles"
+fn nest_profiles(value: Value) -> Result<Value, String> {
+    match value {
+        Value::Object(profiles) => Ok(serde_json::json!({ "profiles": profiles })),
+        _ => Err("expected an object of profiles".to_string()),
     }
+}
+
+impl ConfigStore {
+    /// File name in the config dir
+    pub const FILE: &'static str = "config.json";
 
-    /// Get the config file path (~/.config/gam/config.json)
+    /// Top-level keys of gamm.toml this store owns
+    const KEYS: &'static [&'static str] = &["profiles"];
+
+    /// Get the config file path (~/.config/gamm/config.json)
     pub fn config_path() -> Option<PathBuf> {
-        Self::config_dir().map(|p| p.join("config.json"))
+        storage::config_dir().map(|p| p.join(Self::FILE))
     }
 
     /// Create a new empty store
@@ -58,32 +452,34 @@ impl ConfigStore {
         }
     }
 
-    /// Load store from the default config file, or create new if it doesn't exist
+    /// Load store from the config dir, or create new if it doesn't exist
     pub fn load() -> io::Result<Self> {
-        let path = Self::config_path()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find config directory"))?;
-
-        if path.exists() {
-            let contents = fs::read_to_string(&path)?;
-            serde_json::from_str(&contents)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-        } else {
-            Ok(Self::new())
+        Self::load_from(&storage::require_config_dir()?)
+    }
+
+    /// Load store from config.json or gamm.toml in `dir`
+    pub fn load_from(dir: &Path) -> io::Result<Self> {
+        storage::recover(dir)?;
+        match Format::detect_in(dir) {
+            Format::Toml => document::load(&dir.join(document::FILE)),
+            Format::Json => Ok(SCHEMA.load(&dir.join(Self::FILE))?.unwrap_or_else(Self::new)),
         }
     }
 
-    /// Save store to the default config file
-    pub fn save(&self) -> io::Result<()> {
-        let dir = Self::config_dir()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find config directory"))?;
-        let path = Self::config_path().unwrap();
+    /// Before config.json in `dir` is overwritten, back it up if it has an
+    /// older version
+    pub fn backup_in(dir: &Path) -> io::Result<Option<PathBuf>> {
+        SCHEMA.backup(&dir.join(Self::FILE))
+    }
 
-        // Create directory if it doesn't exist
-        fs::create_dir_all(&dir)?;
+    /// Contents of config.json
+    pub fn to_json(&self) -> io::Result<String> {
+        SCHEMA.to_string(self)
+    }
 
-        let contents = serde_json::to_string_pretty(self)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        fs::write(&path, contents)
+    /// Merge the profiles into a parsed gamm.toml
+    pub fn to_document(&self, doc: &mut DocumentMut, path: &Path) -> io::Result<()> {
+        document::update(doc, path, self, Self::KEYS)
     }
 
     pub fn add(&mut self, name: impl Into<String>, config: GitConfig) {
@@ -94,10 +490,33 @@ impl ConfigStore {
         self.configs.get(name)
     }
 
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut GitConfig> {
+        self.configs.get_mut(name)
+    }
+
     pub fn remove(&mut self, name: &str) -> Option<GitConfig> {
         self.configs.remove(name)
     }
 
+    /// Rename a profile, keeping profiles that extend it pointed at it
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<(), String> {
+        if self.configs.contains_key(new) {
+            return Err(format!("profile '{}' already exists", new));
+        }
+        let config = self
+            .configs
+            .remove(old)
+            .ok_or_else(|| format!("profile '{}' not found", old))?;
+        self.configs.insert(new.to_string(), config);
+
+        for config in self.configs.values_mut() {
+            if config.extends.as_deref() == Some(old) {
+                config.extends = Some(new.to_string());
+            }
+        }
+        Ok(())
+    }
+
     pub fn list(&self) -> impl Iterator<Item = &String> {
         self.configs.keys()
     }
@@ -105,6 +524,46 @@ impl ConfigStore {
     pub fn iter(&self) -> impl Iterator<Item = (&String, &GitConfig)> {
         self.configs.iter()
     }
+
+    /// Resolve a profile together with everything it inherits via `extends`
+    pub fn effective(&self, name: &str) -> Result<GitConfig, String> {
+        let mut config = self
+            .get(name)
+            .ok_or_else(|| format!("profile '{}' not found", name))?
+            .clone();
+        let mut chain = vec![name.to_string()];
+
+        while let Some(base_name) = config.extends.clone() {
+            if chain.contains(&base_name) {
+                chain.push(base_name);
+                return Err(format!("profile inheritance cycle: {}", chain.join(" -> ")));
+            }
+            let base = self.get(&base_name).ok_or_else(|| {
+                format!("profile '{}' extends missing profile '{}'", chain.last().unwrap(), base_name)
+            })?;
+            config = config.merged_over(base);
+            chain.push(base_name);
+        }
+
+        Ok(config)
+    }
+
+    /// Profiles that directly extend `name`
+    pub fn children(&self, name: &str) -> Vec<String> {
+        let mut children: Vec<String> = self
+            .configs
+            .iter()
+            .filter(|(_, config)| config.extends.as_deref() == Some(name))
+            .map(|(child, _)| child.clone())
+            .collect();
+        children.sort();
+        children
+    }
+
+    /// Effective profile, or the profile as declared if it can't be resolved
+    pub fn effective_or_declared(&self, name: &str) -> Option<GitConfig> {
+        self.effective(name).ok().or_else(|| self.get(name).cloned())
+    }
 }
 
 #[cfg(test)]
@@ -123,7 +582,8 @@ mod tests {
                 pattern: "git@github.com:".into(),
                 instead_of: "https://github.com/".into(),
             }],
-            commit: CommitConfig { gpgsign: true },
+            commit: CommitConfig { gpgsign: Some(true) },
+            ..Default::default()
         }
     }
 
@@ -145,7 +605,7 @@ mod tests {
         assert_eq!(config.user.email, "test@example.com");
         assert_eq!(config.user.name, "Test User");
         assert_eq!(config.user.signoff, Some("test".into()));
-        assert!(config.commit.gpgsign);
+        assert_eq!(config.commit.gpgsign, Some(true));
     }
 
     #[test]
@@ -207,32 +667,42 @@ mod tests {
 
     #[test]
     fn test_save_and_load() {
-        let temp_dir = std::env::temp_dir().join("gam_test");
-        let temp_file = temp_dir.join("config.json");
-
-        // Clean up before test
+        let temp_dir = std::env::temp_dir().join(format!("gamm_store_test_{}", std::process::id()));
         let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&temp_dir).unwrap();
 
-        // Create and save store
         let mut store = ConfigStore::new();
         store.add("test", sample_config());
+        fs::create_dir_all(&temp_dir).unwrap();
+        fs::write(temp_dir.join(ConfigStore::FILE), store.to_json().unwrap()).unwrap();
 
-        let json = serde_json::to_string_pretty(&store).unwrap();
-        fs::write(&temp_file, &json).unwrap();
-
-        // Load and verify
-        let contents = fs::read_to_string(&temp_file).unwrap();
-        let loaded: ConfigStore = serde_json::from_str(&contents).unwrap();
-
+        let loaded = ConfigStore::load_from(&temp_dir).unwrap();
         let config = loaded.get("test").unwrap();
         assert_eq!(config.user.email, "test@example.com");
         assert_eq!(config.user.name, "Test User");
 
-        // Clean up
+        // Once gamm.toml exists it is used instead
+        store.add("other", sample_config());
+        document::save(&temp_dir.join(document::FILE), &store, ConfigStore::KEYS).unwrap();
+        assert_eq!(ConfigStore::load_from(&temp_dir).unwrap().iter().count(), 2);
+
         let _ = fs::remove_dir_all(&temp_dir);
     }
 
+    #[test]
+    fn test_legacy_layout_upgrades() {
+        let legacy = r#"{"work": {"user": {"name": "Jane", "email": "jane@acme.com"}}}"#;
+        let (value, from) = SCHEMA.upgrade(serde_json::from_str(legacy).unwrap()).unwrap();
+        assert_eq!(from, 1);
+
+        let store: ConfigStore = serde_json::from_value(value).unwrap();
+        assert_eq!(store.get("work").unwrap().user.email, "jane@acme.com");
+
+        let written = SCHEMA.to_string(&store).unwrap();
+        assert!(written.starts_with("{\n  \"version\": 2,\n  \"profiles\": {"));
+        let (_, from) = SCHEMA.upgrade(serde_json::from_str(&written).unwrap()).unwrap();
+        assert_eq!(from, 2);
+    }
+
     #[test]
     fn test_config_path_exists() {
         // Just verify that config_path returns Some on supported platforms
@@ -241,13 +711,6 @@ mod tests {
         assert!(path.unwrap().ends_with("config.json"));
     }
 
-    #[test]
-    fn test_config_dir_exists() {
-        let dir = ConfigStore::config_dir();
-        assert!(dir.is_some());
-        assert!(dir.unwrap().ends_with("gamm"));
-    }
-
     #[test]
     fn test_user_config_default() {
         let user = UserConfig::default();
@@ -259,7 +722,235 @@ mod tests {
     #[test]
     fn test_commit_config_default() {
         let commit = CommitConfig::default();
-        assert!(!commit.gpgsign);
+        assert!(commit.gpgsign.is_none());
+    }
+
+    #[test]
+    fn test_scope_defaults_to_local_for_legacy_profiles() {
+        let json = r#"{"user":{"email":"a@b.c","name":"A","signoff":null},"urls":[],"commit":{"gpgsign":false}}"#;
+        let config: GitConfig = serde_json::from_str(json).unwrap();
+        assert!(config.scope.is_none());
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("scope"));
+    }
+
+    #[test]
+    fn test_scope_round_trip() {
+        assert_eq!("global".parse::<Scope>(), Ok(Scope::Global));
+        assert_eq!("local".parse::<Scope>(), Ok(Scope::Local));
+        assert!("system".parse::<Scope>().is_err());
+        assert_eq!(Scope::default(), Scope::Local);
+    }
+
+    #[test]
+    fn test_entries() {
+        let entries = sample_config().entries();
+        assert_eq!(
+            entries,
+            vec![
+                ("user.name".to_string(), "Test User".to_string()),
+                ("user.email".to_string(), "test@example.com".to_string()),
+                ("commit.gpgsign".to_string(), "true".to_string()),
+                ("url.git@github.com:.insteadOf".to_string(), "https://github.com/".to_string()),
+            ]
+        );
+
+        // Empty identity fields are left to git's defaults
+        let entries = GitConfig::default().entries();
+        assert_eq!(entries, vec![("commit.gpgsign".to_string(), "false".to_string())]);
+    }
+
+    #[test]
+    fn test_signing_entries() {
+        let mut config = GitConfig {
+            signing: SigningConfig {
+                format: Some(GpgFormat::Ssh),
+                key: Some("~/.ssh/id_ed25519.pub".into()),
+                ssh_program: Some("/usr/bin/ssh-keygen".into()),
+                ..Default::default()
+            },
+            tag: TagConfig { gpgsign: Some(true) },
+            ..Default::default()
+        };
+
+        let entries = config.entries();
+        assert!(entries.contains(&("user.signingkey".into(), "~/.ssh/id_ed25519.pub".into())));
+        assert!(entries.contains(&("gpg.format".into(), "ssh".into())));
+        assert!(entries.contains(&("gpg.ssh.program".into(), "/usr/bin/ssh-keygen".into())));
+        assert!(entries.contains(&("tag.gpgsign".into(), "true".into())));
+
+        // The program key follows the format
+        config.signing.format = Some(GpgFormat::X509);
+        config.signing.program = Some("gpgsm".into());
+        assert!(config.entries().contains(&("gpg.x509.program".into(), "gpgsm".into())));
+    }
+
+    #[test]
+    fn test_ssh_command() {
+        let mut config = GitConfig {
+            ssh_key: Some("~/.ssh/id_work".into()),
+            ..Default::default()
+        };
+        assert!(config.entries().contains(&(
+            "core.sshCommand".into(),
+            "ssh -i ~/.ssh/id_work -o IdentitiesOnly=yes".into()
+        )));
+
+        config.ssh_key = Some("~/my keys/it's".into());
+        assert_eq!(
+            config.ssh_command().as_deref(),
+            Some("ssh -i ~/'my keys/it'\\''s' -o IdentitiesOnly=yes")
+        );
+
+        config.ssh_key = Some("  ".into());
+        assert!(config.ssh_command().is_none());
+
+        assert_eq!(
+            expand_home("~/.ssh/id", Some(Path::new("/home/me"))),
+            PathBuf::from("/home/me/.ssh/id")
+        );
+    }
+
+    #[test]
+    fn test_trailer_templates() {
+        let mut config = sample_config();
+        config.user.signoff = Some("{name} <{email}>".into());
+        config.trailers.push(TrailerConfig::parse("Change-Id: {change_id}").unwrap());
+
+        let trailers = config.trailer_templates();
+        assert_eq!(trailers[0].key, "Signed-off-by");
+        assert_eq!(
+            config.expand(&trailers[0].value, "work", ""),
+            "Test User <test@example.com>"
+        );
+        assert!(!trailers[0].is_generated());
+        assert!(trailers[1].is_generated());
+        assert_eq!(config.expand(&trailers[1].value, "work", "I123"), "I123");
+
+        assert!(TrailerConfig::parse("no colon").is_err());
+        assert!(TrailerConfig::parse("Two words: x").is_err());
+        assert!(TrailerConfig::parse("Key:").is_err());
+        assert!(GitConfig::default().trailer_templates().is_empty());
+    }
+
+    #[test]
+    fn test_multi_valued() {
+        let url = UrlConfig {
+            pattern: "git@gitlab.acme.com:".into(),
+            instead_of: "https://gitlab.acme.com/".into(),
+        };
+        assert!(GitConfig::is_multi_valued(&url.key()));
+        assert!(!GitConfig::is_multi_valued("user.name"));
+    }
+
+    #[test]
+    fn test_extra_keys() {
+        let json = r#"{"user":{"email":"a@b.c","name":"A","signoff":null},"urls":[],"commit":{"gpgsign":false},
+            "extra":{"core.editor":"vim","credential.helper":["","store"]}}"#;
+        let config: GitConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.extra["core.editor"], vec!["vim"]);
+
+        let entries = config.entries();
+        assert!(entries.contains(&("core.editor".into(), "vim".into())));
+        let helpers: Vec<_> = entries.iter().filter(|(k, _)| k == "credential.helper").collect();
+        assert_eq!(helpers.len(), 2);
+
+        assert!(GitConfig::validate_extra_key("init.defaultBranch").is_ok());
+        assert!(GitConfig::validate_extra_key("credential.https://example.com.helper").is_ok());
+        assert!(GitConfig::validate_extra_key("editor").is_err());
+        assert!(GitConfig::validate_extra_key("User.Email").is_err());
+        assert!(GitConfig::validate_extra_key("url.x.insteadOf").is_err());
+        assert!(GitConfig::validate_extra_key("gamm.managed").is_err());
+    }
+
+    #[test]
+    fn test_effective_profile_inherits_unset_fields() {
+        let mut base = sample_config();
+        base.extra.insert("core.editor".into(), vec!["vim".into()]);
+        base.extra.insert("init.defaultBranch".into(), vec!["main".into()]);
+        base.signing.key = Some("BASEKEY".into());
+
+        // A child only needs the fields it overrides
+        let json = r#"{"extends":"base","user":{"email":"a@client.com"}}"#;
+        let mut client: GitConfig = serde_json::from_str(json).unwrap();
+        client.signing.key = Some("CLIENTKEY".into());
+        client.extra.insert("core.editor".into(), vec!["nano".into()]);
+
+        let mut store = ConfigStore::new();
+        store.add("base", base);
+        store.add("client", client);
+
+        let config = store.effective("client").unwrap();
+        assert_eq!(config.user.email, "a@client.com");
+        assert_eq!(config.user.name, "Test User");
+        assert_eq!(config.signing.key.as_deref(), Some("CLIENTKEY"));
+        assert_eq!(config.commit.gpgsign, Some(true));
+        assert_eq!(config.urls.len(), 1);
+        assert_eq!(config.extra["core.editor"], vec!["nano"]);
+        assert_eq!(config.extra["init.defaultBranch"], vec!["main"]);
+        assert!(config.extends.is_none());
+
+        // A child can turn signing off
+        let json = r#"{"extends":"base","commit":{"gpgsign":false},"tag":{"gpgsign":false}}"#;
+        store.add("unsigned", serde_json::from_str(json).unwrap());
+        let config = store.effective("unsigned").unwrap();
+        assert_eq!(config.commit.gpgsign, Some(false));
+        assert!(config.entries().contains(&("commit.gpgsign".into(), "false".into())));
+        store.remove("unsigned");
+
+        assert_eq!(store.children("base"), vec!["client"]);
+        assert!(store.children("client").is_empty());
+    }
+
+    #[test]
+    fn test_effective_profile_errors() {
+        let extends = |base: &str| GitConfig {
+            extends: Some(base.into()),
+            ..Default::default()
+        };
+
+        let mut store = ConfigStore::new();
+        store.add("a", extends("b"));
+        store.add("b", extends("c"));
+        store.add("c", extends("a"));
+        store.add("orphan", extends("gone"));
+
+        assert_eq!(
+            store.effective("a").unwrap_err(),
+            "profile inheritance cycle: a -> b -> c -> a"
+        );
+        assert!(store.effective("orphan").unwrap_err().contains("missing profile 'gone'"));
+        assert!(store.effective("nope").is_err());
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut store = ConfigStore::new();
+        store.add("work", sample_config());
+        store.add("client", GitConfig {
+            extends: Some("work".into()),
+            ..Default::default()
+        });
+
+        store.rename("work", "acme").unwrap();
+        assert!(store.get("work").is_none());
+        assert_eq!(store.get("client").unwrap().extends.as_deref(), Some("acme"));
+        assert_eq!(store.effective("client").unwrap().user.name, "Test User");
+
+        assert!(store.rename("acme", "client").unwrap_err().contains("already exists"));
+        assert!(store.rename("work", "other").unwrap_err().contains("not found"));
+    }
+
+    #[test]
+    fn test_signing_is_optional_in_json() {
+        let json = r#"{"user":{"email":"a@b.c","name":"A","signoff":null},"urls":[],"commit":{"gpgsign":false}}"#;
+        let config: GitConfig = serde_json::from_str(json).unwrap();
+        assert!(config.signing.is_empty());
+        assert!(config.tag.gpgsign.is_none());
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("signing"));
     }
 
     #[test]
@@ -267,6 +958,6 @@ mod tests {
         let config = GitConfig::default();
         assert_eq!(config.user.email, "");
         assert!(config.urls.is_empty());
-        assert!(!config.commit.gpgsign);
+        assert!(config.commit.gpgsign.is_none());
     }
 }
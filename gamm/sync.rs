@@ -0,0 +1,367 @@
+/*
+ * sync.rs
+ * Compile profiles into native git configuration.
+ *
+ * - every profile becomes a gitconfig fragment under <config dir>/profiles
+ * - every repository and rule becomes an `includeIf "hasconfig:remote.*.url:..."`
+ *   entry in a gamm-managed block of ~/.gitconfig pointing at its profile
+ * - every directory rule becomes an `includeIf "gitdir:..."` entry
+ *
+ * Git applies later includes over earlier ones, so entries are written from
+ * the lowest precedence (directory rules, then host rules) to the highest
+ * (exact repositories).
+ *
+ * Git then resolves the identity on its own, without hooks rewriting config.
+ */
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::repo::RepoStore;
+use crate::section;
+use crate::storage;
+use crate::store::{ConfigStore, GitConfig};
+use crate::url::RemoteUrl;
+
+/// What a sync run wrote
+pub struct SyncSummary {
+    pub gitconfig: PathBuf,
+    pub profiles: usize,
+    pub includes: usize,
+    /// Repos and rules skipped because their profile does not exist or
+    /// can't be resolved
+    pub skipped: Vec<String>,
+}
+
+/// Directory holding the generated per-profile fragments
+pub fn fragments_dir() -> Option<PathBuf> {
+    storage::config_dir().map(|p| p.join("profiles"))
+}
+
+/// The global gitconfig git reads, which receives the managed block:
+/// `GIT_CONFIG_GLOBAL` when set, otherwise ~/.gitconfig
+pub fn gitconfig_path() -> Option<PathBuf> {
+    match std::env::var_os("GIT_CONFIG_GLOBAL").filter(|p| !p.is_empty()) {
+        Some(path) => Some(PathBuf::from(path)),
+        None => dirs::home_dir().map(|p| p.join(".gitconfig")),
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "Could not find config directory")
+}
+
+/// File name for a profile fragment, safe for any profile name. Other
+/// characters are percent-encoded (as is a leading dot), so different
+/// profiles never share a file.
+fn fragment_file_name(profile: &str) -> String {
+    let mut safe = String::new();
+    for (i, byte) in profile.bytes().enumerate() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' || (byte == b'.' && i > 0) {
+            safe.push(byte as char);
+        } else {
+            safe.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!("{}.gitconfig", safe)
+}
+
+/// Quote a value for a gitconfig file
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Split `section.subsection.name` into its gitconfig header and variable name
+fn split_key(key: &str) -> (String, &str) {
+    let (section, rest) = key.split_once('.').unwrap_or((key, ""));
+    match rest.rsplit_once('.') {
+        Some((subsection, name)) => (format!("[{} {}]", section, quote(subsection)), name),
+        None => (format!("[{}]", section), rest),
+    }
+}
+
+/// Render a profile as a gitconfig fragment
+pub fn render_fragment(profile: &str, config: &GitConfig) -> String {
+    let mut sections: Vec<(String, Vec<String>)> = Vec::new();
+
+    for (key, value) in config.entries() {
+        let (header, name) = split_key(&key);
+        let line = format!("\t{} = {}", name, quote(&value));
+        match sections.iter_mut().find(|(h, _)| *h == header) {
+            Some((_, lines)) => lines.push(line),
+            None => sections.push((header, vec![line])),
+        }
+    }
+
+    let mut out = format!("# Generated by `gamm sync` for profile '{}'. Do not edit.\n", profile);
+    for (header, lines) in sections {
+        out.push_str(&header);
+        out.push('\n');
+        for line in lines {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// URL globs matching every common remote form of a rule pattern
+fn rule_url_globs(pattern: &str) -> Vec<String> {
+    let pattern = pattern.trim_end_matches('/');
+    let (host, path) = pattern.split_once('/').unwrap_or((pattern, "**"));
+    vec![
+        format!("https://{}/{}", host, path),
+        format!("ssh://git@{}/{}", host, path),
+        format!("git@{}:{}", host, path),
+    ]
+}
+
+/// `gitdir:` condition for a directory rule. Git matches it against the
+/// repository's .git directory, so the pattern is extended below the top level.
+fn gitdir_condition(pattern: &str) -> String {
+    let pattern = pattern.trim_end_matches('/');
+    if pattern.ends_with("**") {
+        format!("gitdir:{}", pattern)
+    } else {
+        format!("gitdir:{}/**", pattern)
+    }
+}
+
+/// Render the managed ~/.gitconfig block from (condition, fragment) pairs
+pub fn render_includes(includes: &[(String, PathBuf)]) -> String {
+    let mut out = String::from("# Managed by `gamm sync`. Changes inside this block are overwritten.\n");
+    for (condition, path) in includes {
+        out.push_str(&format!("[includeIf {}]\n", quote(condition)));
+        out.push_str(&format!("\tpath = {}\n", quote(&path.display().to_string())));
+    }
+    out
+}
+
+/// Check whether ~/.gitconfig currently contains a gamm-managed block
+pub fn is_enabled() -> bool {
+    gitconfig_path()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .is_some_and(|c| section::contains(&c))
+}
+
+/// Write all profile fragments and rewrite the managed ~/.gitconfig block
+pub fn write(config_store: &ConfigStore, repo_store: &RepoStore) -> io::Result<SyncSummary> {
+    let dir = fragments_dir().ok_or_else(not_found)?;
+    let gitconfig = gitconfig_path().ok_or_else(not_found)?;
+
+    // A GIT_CONFIG_GLOBAL like /dev/null can't hold includes
+    if gitconfig.exists() && !gitconfig.is_file() {
+        return Err(io::Error::other(format!(
+            "git's global config {} is not a file; point GIT_CONFIG_GLOBAL at a file to sync",
+            gitconfig.display()
+        )));
+    }
+
+    fs::create_dir_all(&dir)?;
+
+    // Fragments hold the effective profile, inheritance included. A profile
+    // whose `extends` chain is broken gets no fragment. Fragments are
+    // replaced in place, so the includes never point at a missing file.
+    let mut written = HashSet::new();
+    for (name, _) in config_store.iter() {
+        if let Ok(config) = config_store.effective(name) {
+            storage::write_atomic(&dir.join(fragment_file_name(name)), render_fragment(name, &config).as_bytes())?;
+            written.insert(name.as_str());
+        }
+    }
+
+    // Sort for a stable file across runs
+    let mut repos: Vec<_> = repo_store.iter().map(|(_, r)| r).collect();
+    repos.sort_by(|a, b| a.url.cmp(&b.url));
+
+    let mut includes = Vec::new();
+    let mut skipped = Vec::new();
+
+    // Directory rules are the fallback, so they go first
+    for rule in repo_store.dir_rules_by_precedence().into_iter().rev() {
+        if !written.contains(rule.commit_by.as_str()) {
+            skipped.push(rule.pattern.clone());
+            continue;
+        }
+        includes.push((
+            gitdir_condition(&rule.pattern),
+            dir.join(fragment_file_name(&rule.commit_by)),
+        ));
+    }
+
+    for rule in repo_store.rules_by_precedence().into_iter().rev() {
+        if !written.contains(rule.commit_by.as_str()) {
+            skipped.push(rule.pattern.clone());
+            continue;
+        }
+        for glob in rule_url_globs(&rule.pattern) {
+            includes.push((
+                format!("hasconfig:remote.*.url:{}", glob),
+                dir.join(fragment_file_name(&rule.commit_by)),
+            ));
+        }
+    }
+
+    for repo in repos {
+        if !written.contains(repo.commit_by.as_str()) {
+            skipped.push(repo.repo_name.clone());
+            continue;
+        }
+
+        // Entries match every spelling of the URL, so list them all
+        let mut urls = vec![repo.url.clone()];
+        if let Some(remote) = RemoteUrl::parse(&repo.url) {
+            urls.extend(remote.variants().into_iter().filter(|v| *v != repo.url));
+        }
+        for url in urls {
+            includes.push((
+                format!("hasconfig:remote.*.url:{}", url),
+                dir.join(fragment_file_name(&repo.commit_by)),
+            ));
+        }
+    }
+
+    let existing = if gitconfig.exists() {
+        fs::read_to_string(&gitconfig)?
+    } else {
+        String::new()
+    };
+    storage::write_atomic(&gitconfig, section::upsert(&existing, &render_includes(&includes)).as_bytes())?;
+
+    // Only now that nothing refers to them, drop fragments of deleted profiles
+    let keep: HashSet<String> = written.iter().map(|name| fragment_file_name(name)).collect();
+    remove_fragments(&dir, &keep)?;
+
+    Ok(SyncSummary {
+        gitconfig,
+        profiles: written.len(),
+        includes: includes.len(),
+        skipped,
+    })
+}
+
+/// Re-run sync after the stores changed, but only if the user opted in
+pub fn refresh(config_store: &ConfigStore, repo_store: &RepoStore) -> io::Result<()> {
+    if is_enabled() {
+        write(config_store, repo_store)?;
+    }
+    Ok(())
+}
+
+/// Remove the managed ~/.gitconfig block and all generated fragments
+pub fn remove() -> io::Result<()> {
+    if let Some(gitconfig) = gitconfig_path()
+        && gitconfig.exists()
+    {
+        let content = fs::read_to_string(&gitconfig)?;
+        if section::contains(&content) {
+            let rest = section::strip(&content);
+            let rest = if rest.is_empty() { rest } else { format!("{}\n", rest) };
+            storage::write_atomic(&gitconfig, rest.as_bytes())?;
+            println!("Removed gamm includes from: {}", gitconfig.display());
+        }
+    }
+
+    if let Some(dir) = fragments_dir()
+        && dir.exists()
+    {
+        remove_fragments(&dir, &HashSet::new())?;
+        if dir.read_dir()?.next().is_none() {
+            fs::remove_dir(&dir)?;
+        }
+        println!("Removed profile fragments: {}", dir.display());
+    }
+
+    Ok(())
+}
+
+/// Delete the generated fragments in the directory whose file name isn't in `keep`
+fn remove_fragments(dir: &Path, keep: &HashSet<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "gitconfig")
+            && !path.file_name().is_some_and(|n| keep.contains(n.to_string_lossy().as_ref()))
+        {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{CommitConfig, UrlConfig, UserConfig};
+
+    #[test]
+    fn test_render_fragment_groups_sections() {
+        let config = GitConfig {
+            user: UserConfig {
+                name: "Jane \"JD\" Doe".into(),
+                email: "jane@work.com".into(),
+                signoff: None,
+            },
+            urls: vec![UrlConfig {
+                pattern: "git@github.com:".into(),
+                instead_of: "https://github.com/".into(),
+            }],
+            commit: CommitConfig { gpgsign: Some(true) },
+            ..Default::default()
+        };
+
+        let fragment = render_fragment("work", &config);
+        assert!(fragment.contains(
+            "[user]\n\tname = \"Jane \\\"JD\\\" Doe\"\n\temail = \"jane@work.com\"\n"
+        ));
+        assert!(fragment.contains("[commit]\n\tgpgsign = \"true\"\n"));
+        assert!(fragment.contains(
+            "[url \"git@github.com:\"]\n\tinsteadOf = \"https://github.com/\"\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_includes() {
+        let includes = vec![(
+            "hasconfig:remote.*.url:git@github.com:acme/api.git".to_string(),
+            PathBuf::from("/home/me/.config/gamm/profiles/work.gitconfig"),
+        )];
+        let block = render_includes(&includes);
+        assert!(block.contains(
+            "[includeIf \"hasconfig:remote.*.url:git@github.com:acme/api.git\"]\n\tpath = \"/home/me/.config/gamm/profiles/work.gitconfig\"\n"
+        ));
+    }
+
+    #[test]
+    fn test_rule_url_globs() {
+        assert_eq!(
+            rule_url_globs("github.com/acme/*"),
+            vec![
+                "https://github.com/acme/*",
+                "ssh://git@github.com/acme/*",
+                "git@github.com:acme/*",
+            ]
+        );
+        assert_eq!(rule_url_globs("gitlab.acme.internal")[0], "https://gitlab.acme.internal/**");
+    }
+
+    #[test]
+    fn test_gitdir_condition() {
+        assert_eq!(gitdir_condition("~/work/**"), "gitdir:~/work/**");
+        assert_eq!(gitdir_condition("~/oss/*"), "gitdir:~/oss/*/**");
+        assert_eq!(gitdir_condition("/srv/repos/tool/"), "gitdir:/srv/repos/tool/**");
+    }
+
+    #[test]
+    fn test_fragment_file_name_is_path_safe() {
+        assert_eq!(fragment_file_name("work"), "work.gitconfig");
+        assert_eq!(fragment_file_name("client.v2"), "client.v2.gitconfig");
+        assert_eq!(fragment_file_name("../client a"), "%2E.%2Fclient%20a.gitconfig");
+        assert_eq!(fragment_file_name("café"), "caf%C3%A9.gitconfig");
+
+        // Names that only differ in unsafe characters get their own files
+        assert_ne!(fragment_file_name("a b"), fragment_file_name("a_b"));
+        assert_ne!(fragment_file_name("a%20b"), fragment_file_name("a b"));
+    }
+}
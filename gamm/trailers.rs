@@ -0,0 +1,77 @@
+/*
+ * trailers.rs
+ * Sign-off and trailers added to commit messages for a profile.
+ *
+ * Both the prepare-commit-msg hook (message given with -m/-F, amend, merge)
+ * and the commit-msg hook (message written in the editor) call `add`; it is
+ * idempotent, so a trailer is never added twice. Empty messages are left
+ * alone so git can still abort the commit.
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::git;
+use crate::store::GitConfig;
+
+/// Line git puts above the diff in verbose commit messages
+const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+/// Check whether a commit message has any text besides comments
+fn has_content(message: &str) -> bool {
+    message
+        .lines()
+        .take_while(|l| *l != SCISSORS)
+        .any(|l| !l.trim().is_empty() && !l.starts_with('#'))
+}
+
+/// Generate a Gerrit-style Change-Id
+fn change_id(profile: &str, message: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let seed = format!("{}\n{}\n{}\n{}", profile, nanos, std::process::id(), message);
+    format!("I{}", git::hash_object(&seed).unwrap_or_else(|| format!("{:040x}", nanos)))
+}
+
+/// Add the profile's trailers to a commit message file. Returns the number
+/// of trailers considered (0 if the profile has none or the message is empty).
+pub fn add(file: &Path, profile: &str, config: &GitConfig) -> io::Result<usize> {
+    let templates = config.trailer_templates();
+    if templates.is_empty() {
+        return Ok(0);
+    }
+
+    let message = fs::read_to_string(file)?;
+    if !has_content(&message) {
+        return Ok(0);
+    }
+
+    let mut id = None;
+    for template in &templates {
+        let (change, if_exists) = if template.is_generated() {
+            (id.get_or_insert_with(|| change_id(profile, &message)).as_str(), "doNothing")
+        } else {
+            ("", "addIfDifferent")
+        };
+        let value = config.expand(&template.value, profile, change);
+        git::add_trailer(file, &format!("{}: {}", template.key, value), if_exists)?;
+    }
+
+    Ok(templates.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_content_ignores_comments_and_diff() {
+        assert!(!has_content("\n# Please enter the commit message\n#\n"));
+        assert!(!has_content(&format!("\n# comment\n{}\ndiff --git a/x b/x\n", SCISSORS)));
+        assert!(has_content("Fix the thing\n\n# comment\n"));
+    }
+}